@@ -0,0 +1,354 @@
+//! Flat form of the tree where every node lives in a `SlotMap` and refers
+//! to its children by key. Keys are stable, so later passes can keep what
+//! they learn about a node in a `SideTable` instead of rewriting the tree
+
+use core::ops::Index;
+
+use slotmap::{new_key_type, SecondaryMap, SlotMap};
+
+use crate::{Ast, Expr, Function, InlineAsm, Namespace, NumberLiteral, Parameter, Path, Span, Spanned, Type};
+
+new_key_type! {
+    pub struct ExprId;
+    pub struct ItemId;
+}
+
+/// Per node data computed by a pass, keyed by `ExprId` or `ItemId`
+pub type SideTable<K, V> = SecondaryMap<K, V>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Eq,
+    NotEq,
+    Greater,
+    Less,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+/// `Expr` with its children replaced by keys
+#[derive(Debug, Clone)]
+pub enum Node {
+    Number(NumberLiteral),
+    Char(u8),
+    Str(Vec<u8>),
+    Ident(String),
+    Binary(BinaryOp, ExprId, ExprId),
+    Unary(UnaryOp, ExprId),
+    Cast(ExprId, Spanned<Type>),
+    Decl(String, Spanned<Type>, ExprId),
+    Assign(String, ExprId),
+    Call(Path, Vec<ExprId>),
+    Block(Vec<ExprId>),
+    If(ExprId, ExprId, ExprId),
+    While(ExprId, ExprId),
+    For(ExprId, ExprId, ExprId, ExprId),
+    Return(ExprId),
+    Break,
+    Continue,
+    /// Function or namespace written where an expression was expected
+    Item(ItemId),
+    InlineAsm(InlineAsm),
+    Empty,
+}
+
+#[derive(Debug, Clone)]
+pub enum Item {
+    Function {
+        name: String,
+        ret: Spanned<Type>,
+        params: Vec<Parameter>,
+        /// `None` for prototypes
+        body: Option<ExprId>,
+        doc: Option<String>,
+    },
+    Namespace {
+        name: String,
+        items: Vec<ItemId>,
+        doc: Option<String>,
+    },
+}
+
+impl Item {
+    pub fn name(&self) -> &str {
+        match self {
+            Item::Function { name, .. } | Item::Namespace { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Arena {
+    pub exprs: SlotMap<ExprId, Spanned<Node>>,
+    pub items: SlotMap<ItemId, Spanned<Item>>,
+    /// Top level items in source order
+    pub root: Vec<ItemId>,
+}
+
+impl Arena {
+    /// Moves every node of `ast` into the arena. Only functions and
+    /// namespaces are kept at the top level, as the parser produces
+    pub fn lower(ast: &Ast) -> Self {
+        let mut arena = Self::default();
+        arena.root = ast
+            .root
+            .iter()
+            .filter_map(|item| arena.lower_item(item))
+            .collect();
+        arena
+    }
+
+    fn lower_item(&mut self, item: &Spanned<Expr>) -> Option<ItemId> {
+        let Spanned(span, item) = item;
+        match item {
+            Expr::Function(func) => Some(self.lower_function(span, func)),
+            Expr::Namespace(namespace) => Some(self.lower_namespace(span, namespace)),
+            _ => None,
+        }
+    }
+
+    fn lower_function(&mut self, span: &Span, func: &Function) -> ItemId {
+        let item = Item::Function {
+            name: func.name.clone(),
+            ret: func.ret.clone(),
+            params: func.params.clone(),
+            body: func.body.as_ref().map(|body| self.lower_expr(body)),
+            doc: func.doc.clone(),
+        };
+        self.items.insert(Spanned(span.clone(), item))
+    }
+
+    fn lower_namespace(&mut self, span: &Span, namespace: &Namespace) -> ItemId {
+        let item = Item::Namespace {
+            name: namespace.name.clone(),
+            items: namespace
+                .items
+                .iter()
+                .filter_map(|item| self.lower_item(item))
+                .collect(),
+            doc: namespace.doc.clone(),
+        };
+        self.items.insert(Spanned(span.clone(), item))
+    }
+
+    fn lower_expr(&mut self, expr: &Spanned<Expr>) -> ExprId {
+        let Spanned(span, expr) = expr;
+        let node = match expr {
+            Expr::Number(literal) => Node::Number(literal.clone()),
+            Expr::Char(value) => Node::Char(*value),
+            Expr::Str(value) => Node::Str(value.clone()),
+            Expr::Ident(name) => Node::Ident(name.clone()),
+            Expr::Add(lhs, rhs) => self.lower_binary(BinaryOp::Add, lhs, rhs),
+            Expr::Sub(lhs, rhs) => self.lower_binary(BinaryOp::Sub, lhs, rhs),
+            Expr::Mul(lhs, rhs) => self.lower_binary(BinaryOp::Mul, lhs, rhs),
+            Expr::Div(lhs, rhs) => self.lower_binary(BinaryOp::Div, lhs, rhs),
+            Expr::Pow(lhs, rhs) => self.lower_binary(BinaryOp::Pow, lhs, rhs),
+            Expr::Eq(lhs, rhs) => self.lower_binary(BinaryOp::Eq, lhs, rhs),
+            Expr::NotEq(lhs, rhs) => self.lower_binary(BinaryOp::NotEq, lhs, rhs),
+            Expr::Greater(lhs, rhs) => self.lower_binary(BinaryOp::Greater, lhs, rhs),
+            Expr::Less(lhs, rhs) => self.lower_binary(BinaryOp::Less, lhs, rhs),
+            Expr::Neg(term) => Node::Unary(UnaryOp::Neg, self.lower_expr(term)),
+            Expr::Not(term) => Node::Unary(UnaryOp::Not, self.lower_expr(term)),
+            Expr::Cast(value, ty) => Node::Cast(self.lower_expr(value), ty.clone()),
+            Expr::Decl(name, ty, value) => Node::Decl(name.clone(), ty.clone(), self.lower_expr(value)),
+            Expr::Assign(name, value) => Node::Assign(name.clone(), self.lower_expr(value)),
+            Expr::Call(path, args) => Node::Call(path.clone(), self.lower_list(args)),
+            Expr::Block(stats) => Node::Block(self.lower_list(stats)),
+            Expr::If(cond, then, otherwise) => Node::If(
+                self.lower_expr(cond),
+                self.lower_expr(then),
+                self.lower_expr(otherwise),
+            ),
+            Expr::While(cond, body) => Node::While(self.lower_expr(cond), self.lower_expr(body)),
+            Expr::For(init, cond, step, body) => Node::For(
+                self.lower_expr(init),
+                self.lower_expr(cond),
+                self.lower_expr(step),
+                self.lower_expr(body),
+            ),
+            Expr::Return(value) => Node::Return(self.lower_expr(value)),
+            Expr::Break => Node::Break,
+            Expr::Continue => Node::Continue,
+            Expr::Function(func) => Node::Item(self.lower_function(span, func)),
+            Expr::Namespace(namespace) => Node::Item(self.lower_namespace(span, namespace)),
+            Expr::InlineAsm(asm) => Node::InlineAsm(asm.clone()),
+            Expr::Empty => Node::Empty,
+        };
+        self.exprs.insert(Spanned(span.clone(), node))
+    }
+
+    fn lower_binary(&mut self, op: BinaryOp, lhs: &Spanned<Expr>, rhs: &Spanned<Expr>) -> Node {
+        Node::Binary(op, self.lower_expr(lhs), self.lower_expr(rhs))
+    }
+
+    fn lower_list(&mut self, exprs: &[Spanned<Expr>]) -> Vec<ExprId> {
+        exprs.iter().map(|expr| self.lower_expr(expr)).collect()
+    }
+
+    pub fn span(&self, id: ExprId) -> &Span {
+        &self.exprs[id].0
+    }
+
+    /// Direct children of `id` in evaluation order
+    pub fn children(&self, id: ExprId) -> impl Iterator<Item = ExprId> + '_ {
+        let (fixed, list): ([Option<ExprId>; 4], &[ExprId]) = match &self[id] {
+            Node::Binary(_, lhs, rhs) | Node::While(lhs, rhs) => ([Some(*lhs), Some(*rhs), None, None], &[]),
+            Node::Unary(_, term)
+            | Node::Cast(term, _)
+            | Node::Decl(_, _, term)
+            | Node::Assign(_, term)
+            | Node::Return(term) => ([Some(*term), None, None, None], &[]),
+            Node::Call(_, exprs) | Node::Block(exprs) => ([None; 4], exprs),
+            Node::If(cond, then, otherwise) => ([Some(*cond), Some(*then), Some(*otherwise), None], &[]),
+            Node::For(init, cond, step, body) => ([Some(*init), Some(*cond), Some(*step), Some(*body)], &[]),
+            Node::Number(_)
+            | Node::Char(_)
+            | Node::Str(_)
+            | Node::Ident(_)
+            | Node::Break
+            | Node::Continue
+            | Node::Item(_)
+            | Node::InlineAsm(_)
+            | Node::Empty => ([None; 4], &[]),
+        };
+        fixed.into_iter().flatten().chain(list.iter().copied())
+    }
+}
+
+impl Index<ExprId> for Arena {
+    type Output = Node;
+
+    fn index(&self, id: ExprId) -> &Node {
+        &self.exprs[id].1
+    }
+}
+
+impl Index<ItemId> for Arena {
+    type Output = Item;
+
+    fn index(&self, id: ItemId) -> &Item {
+        &self.items[id].1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `namespace m { func f(a:i16) > i16 { return a + 1; } }`
+    fn sample() -> Ast {
+        let sum = Expr::Add(
+            Box::new(Spanned(49..50, Expr::Ident("a".into()))),
+            Box::new(Spanned(53..54, Expr::Number(1.into()))),
+        );
+        let body = Expr::Block(vec![Spanned(42..55, Expr::Return(Box::new(Spanned(49..54, sum))))]);
+        let func = Expr::Function(Function {
+            name: "f".into(),
+            ret: Spanned(36..39, Type::I16),
+            params: vec![(Spanned(21..22, "a".into()), Spanned(27..30, Type::I16))],
+            body: Some(Box::new(Spanned(40..57, body))),
+            doc: None,
+        });
+        Ast {
+            root: vec![Spanned(
+                0..59,
+                Expr::Namespace(Namespace {
+                    name: "m".into(),
+                    items: vec![Spanned(14..57, func)],
+                    doc: None,
+                }),
+            )],
+        }
+    }
+
+    #[test]
+    fn lower_into_arena() {
+        let arena = Arena::lower(&sample());
+        assert_eq!(arena.root.len(), 1);
+        assert_eq!(arena.items.len(), 2);
+        assert_eq!(arena.exprs.len(), 5);
+
+        let Item::Namespace { items, .. } = &arena[arena.root[0]] else {
+            panic!("expected a namespace");
+        };
+        let Item::Function { name, body: Some(body), .. } = &arena[items[0]] else {
+            panic!("expected a function");
+        };
+        assert_eq!(name, "f");
+        assert_eq!(arena.span(*body), &(40..57));
+
+        let ret = arena.children(*body).next().unwrap();
+        let sum = arena.children(ret).next().unwrap();
+        assert!(matches!(arena[sum], Node::Binary(BinaryOp::Add, _, _)));
+        let [lhs, rhs] = arena.children(sum).collect::<Vec<_>>()[..] else {
+            panic!("expected two operands");
+        };
+        assert!(matches!(&arena[lhs], Node::Ident(name) if name == "a"));
+        assert_eq!(arena.span(rhs), &(53..54));
+    }
+
+    #[test]
+    fn side_tables_outlive_lookups() {
+        let arena = Arena::lower(&sample());
+        let mut depth: SideTable<ExprId, usize> = SideTable::new();
+        let Item::Namespace { items, .. } = &arena[arena.root[0]] else {
+            unreachable!()
+        };
+        let Item::Function { body: Some(body), .. } = &arena[items[0]] else {
+            unreachable!()
+        };
+        let mut stack = vec![(*body, 0)];
+        while let Some((id, level)) = stack.pop() {
+            depth.insert(id, level);
+            stack.extend(arena.children(id).map(|child| (child, level + 1)));
+        }
+        assert_eq!(depth.len(), arena.exprs.len());
+        let leaves = arena
+            .exprs
+            .keys()
+            .filter(|id| arena.children(*id).next().is_none())
+            .map(|id| depth[id])
+            .collect::<Vec<_>>();
+        assert_eq!(leaves, [3, 3]);
+    }
+
+    #[test]
+    fn nested_items_lower_in_place() {
+        let Spanned(_, Expr::Namespace(namespace)) = &sample().root[0] else {
+            unreachable!()
+        };
+        let nested = Spanned(0..60, Expr::Block(namespace.items.clone()));
+        let func = Expr::Function(Function {
+            name: "g".into(),
+            ret: Spanned(0..0, Type::Void),
+            params: Vec::new(),
+            body: Some(Box::new(nested)),
+            doc: None,
+        });
+        let arena = Arena::lower(&Ast {
+            root: vec![Spanned(0..70, func)],
+        });
+
+        let Item::Function { body: Some(body), .. } = &arena[arena.root[0]] else {
+            unreachable!()
+        };
+        let Node::Block(stats) = &arena[*body] else {
+            panic!("expected a block");
+        };
+        let Node::Item(inner) = arena[stats[0]] else {
+            panic!("expected an item");
+        };
+        assert_eq!(arena.items[inner].0, 14..57);
+        assert!(matches!(&arena[inner], Item::Function { name, body: Some(_), .. } if name == "f"));
+        assert_eq!(arena.items.len(), 2);
+    }
+}
@@ -0,0 +1,341 @@
+//! Owned traversal rebuilding the tree, one hook per kind of node. Hooks
+//! of nodes with children default to the `walk_*` function that folds them
+//! and rebuilds the node, hooks of leaves return the node unchanged
+
+use crate::{
+    arena::{BinaryOp, UnaryOp},
+    Ast, Expr, Function, InlineAsm, Namespace, NumberLiteral, Path, Span, Spanned, Type,
+};
+
+pub trait Fold {
+    fn fold_ast(&mut self, ast: Ast) -> Ast {
+        walk_ast(self, ast)
+    }
+
+    fn fold_expr(&mut self, expr: Spanned<Expr>) -> Spanned<Expr> {
+        walk_expr(self, expr)
+    }
+
+    fn fold_number(&mut self, number: NumberLiteral, _span: &Span) -> Expr {
+        Expr::Number(number)
+    }
+
+    fn fold_char(&mut self, value: u8, _span: &Span) -> Expr {
+        Expr::Char(value)
+    }
+
+    fn fold_str(&mut self, value: Vec<u8>, _span: &Span) -> Expr {
+        Expr::Str(value)
+    }
+
+    fn fold_ident(&mut self, name: String, _span: &Span) -> Expr {
+        Expr::Ident(name)
+    }
+
+    fn fold_binary(&mut self, op: BinaryOp, lhs: Box<Spanned<Expr>>, rhs: Box<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_binary(self, op, lhs, rhs, span)
+    }
+
+    fn fold_unary(&mut self, op: UnaryOp, term: Box<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_unary(self, op, term, span)
+    }
+
+    fn fold_cast(&mut self, value: Box<Spanned<Expr>>, ty: Spanned<Type>, span: &Span) -> Expr {
+        walk_cast(self, value, ty, span)
+    }
+
+    fn fold_decl(&mut self, name: String, ty: Spanned<Type>, value: Box<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_decl(self, name, ty, value, span)
+    }
+
+    fn fold_assign(&mut self, name: String, value: Box<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_assign(self, name, value, span)
+    }
+
+    fn fold_call(&mut self, path: Path, args: Vec<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_call(self, path, args, span)
+    }
+
+    fn fold_block(&mut self, stats: Vec<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_block(self, stats, span)
+    }
+
+    fn fold_if(
+        &mut self,
+        cond: Box<Spanned<Expr>>,
+        then: Box<Spanned<Expr>>,
+        otherwise: Box<Spanned<Expr>>,
+        span: &Span,
+    ) -> Expr {
+        walk_if(self, cond, then, otherwise, span)
+    }
+
+    fn fold_while(&mut self, cond: Box<Spanned<Expr>>, body: Box<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_while(self, cond, body, span)
+    }
+
+    fn fold_for(
+        &mut self,
+        init: Box<Spanned<Expr>>,
+        cond: Box<Spanned<Expr>>,
+        step: Box<Spanned<Expr>>,
+        body: Box<Spanned<Expr>>,
+        span: &Span,
+    ) -> Expr {
+        walk_for(self, init, cond, step, body, span)
+    }
+
+    fn fold_return(&mut self, value: Box<Spanned<Expr>>, span: &Span) -> Expr {
+        walk_return(self, value, span)
+    }
+
+    fn fold_function(&mut self, func: Function, span: &Span) -> Function {
+        walk_function(self, func, span)
+    }
+
+    fn fold_namespace(&mut self, namespace: Namespace, span: &Span) -> Namespace {
+        walk_namespace(self, namespace, span)
+    }
+
+    fn fold_type(&mut self, ty: Spanned<Type>) -> Spanned<Type> {
+        ty
+    }
+
+    fn fold_inline_asm(&mut self, asm: InlineAsm, _span: &Span) -> InlineAsm {
+        asm
+    }
+}
+
+pub fn walk_ast<F: Fold + ?Sized>(folder: &mut F, ast: Ast) -> Ast {
+    Ast {
+        root: ast.root.into_iter().map(|item| folder.fold_expr(item)).collect(),
+    }
+}
+
+/// Folds a boxed child in place, reusing its allocation
+fn fold_box<F: Fold + ?Sized>(folder: &mut F, mut expr: Box<Spanned<Expr>>) -> Box<Spanned<Expr>> {
+    let node = std::mem::replace(&mut *expr, Spanned(0..0, Expr::Empty));
+    *expr = folder.fold_expr(node);
+    expr
+}
+
+fn fold_list<F: Fold + ?Sized>(folder: &mut F, exprs: Vec<Spanned<Expr>>) -> Vec<Spanned<Expr>> {
+    exprs.into_iter().map(|expr| folder.fold_expr(expr)).collect()
+}
+
+pub fn walk_expr<F: Fold + ?Sized>(folder: &mut F, expr: Spanned<Expr>) -> Spanned<Expr> {
+    let Spanned(span, expr) = expr;
+    let expr = match expr {
+        Expr::Number(number) => folder.fold_number(number, &span),
+        Expr::Char(value) => folder.fold_char(value, &span),
+        Expr::Str(value) => folder.fold_str(value, &span),
+        Expr::Ident(name) => folder.fold_ident(name, &span),
+        Expr::Add(lhs, rhs) => folder.fold_binary(BinaryOp::Add, lhs, rhs, &span),
+        Expr::Sub(lhs, rhs) => folder.fold_binary(BinaryOp::Sub, lhs, rhs, &span),
+        Expr::Mul(lhs, rhs) => folder.fold_binary(BinaryOp::Mul, lhs, rhs, &span),
+        Expr::Div(lhs, rhs) => folder.fold_binary(BinaryOp::Div, lhs, rhs, &span),
+        Expr::Pow(lhs, rhs) => folder.fold_binary(BinaryOp::Pow, lhs, rhs, &span),
+        Expr::Eq(lhs, rhs) => folder.fold_binary(BinaryOp::Eq, lhs, rhs, &span),
+        Expr::NotEq(lhs, rhs) => folder.fold_binary(BinaryOp::NotEq, lhs, rhs, &span),
+        Expr::Greater(lhs, rhs) => folder.fold_binary(BinaryOp::Greater, lhs, rhs, &span),
+        Expr::Less(lhs, rhs) => folder.fold_binary(BinaryOp::Less, lhs, rhs, &span),
+        Expr::Neg(term) => folder.fold_unary(UnaryOp::Neg, term, &span),
+        Expr::Not(term) => folder.fold_unary(UnaryOp::Not, term, &span),
+        Expr::Cast(value, ty) => folder.fold_cast(value, ty, &span),
+        Expr::Decl(name, ty, value) => folder.fold_decl(name, ty, value, &span),
+        Expr::Assign(name, value) => folder.fold_assign(name, value, &span),
+        Expr::Call(path, args) => folder.fold_call(path, args, &span),
+        Expr::Block(stats) => folder.fold_block(stats, &span),
+        Expr::If(cond, then, otherwise) => folder.fold_if(cond, then, otherwise, &span),
+        Expr::While(cond, body) => folder.fold_while(cond, body, &span),
+        Expr::For(init, cond, step, body) => folder.fold_for(init, cond, step, body, &span),
+        Expr::Return(value) => folder.fold_return(value, &span),
+        Expr::Function(func) => Expr::Function(folder.fold_function(func, &span)),
+        Expr::Namespace(namespace) => Expr::Namespace(folder.fold_namespace(namespace, &span)),
+        Expr::InlineAsm(asm) => Expr::InlineAsm(folder.fold_inline_asm(asm, &span)),
+        leaf @ (Expr::Break | Expr::Continue | Expr::Empty) => leaf,
+    };
+    Spanned(span, expr)
+}
+
+pub fn walk_binary<F: Fold + ?Sized>(
+    folder: &mut F,
+    op: BinaryOp,
+    lhs: Box<Spanned<Expr>>,
+    rhs: Box<Spanned<Expr>>,
+    _span: &Span,
+) -> Expr {
+    let (lhs, rhs) = (fold_box(folder, lhs), fold_box(folder, rhs));
+    match op {
+        BinaryOp::Add => Expr::Add(lhs, rhs),
+        BinaryOp::Sub => Expr::Sub(lhs, rhs),
+        BinaryOp::Mul => Expr::Mul(lhs, rhs),
+        BinaryOp::Div => Expr::Div(lhs, rhs),
+        BinaryOp::Pow => Expr::Pow(lhs, rhs),
+        BinaryOp::Eq => Expr::Eq(lhs, rhs),
+        BinaryOp::NotEq => Expr::NotEq(lhs, rhs),
+        BinaryOp::Greater => Expr::Greater(lhs, rhs),
+        BinaryOp::Less => Expr::Less(lhs, rhs),
+    }
+}
+
+pub fn walk_unary<F: Fold + ?Sized>(folder: &mut F, op: UnaryOp, term: Box<Spanned<Expr>>, _span: &Span) -> Expr {
+    let term = fold_box(folder, term);
+    match op {
+        UnaryOp::Neg => Expr::Neg(term),
+        UnaryOp::Not => Expr::Not(term),
+    }
+}
+
+pub fn walk_cast<F: Fold + ?Sized>(folder: &mut F, value: Box<Spanned<Expr>>, ty: Spanned<Type>, _span: &Span) -> Expr {
+    Expr::Cast(fold_box(folder, value), folder.fold_type(ty))
+}
+
+pub fn walk_decl<F: Fold + ?Sized>(
+    folder: &mut F,
+    name: String,
+    ty: Spanned<Type>,
+    value: Box<Spanned<Expr>>,
+    _span: &Span,
+) -> Expr {
+    Expr::Decl(name, folder.fold_type(ty), fold_box(folder, value))
+}
+
+pub fn walk_assign<F: Fold + ?Sized>(folder: &mut F, name: String, value: Box<Spanned<Expr>>, _span: &Span) -> Expr {
+    Expr::Assign(name, fold_box(folder, value))
+}
+
+pub fn walk_call<F: Fold + ?Sized>(folder: &mut F, path: Path, args: Vec<Spanned<Expr>>, _span: &Span) -> Expr {
+    Expr::Call(path, fold_list(folder, args))
+}
+
+pub fn walk_block<F: Fold + ?Sized>(folder: &mut F, stats: Vec<Spanned<Expr>>, _span: &Span) -> Expr {
+    Expr::Block(fold_list(folder, stats))
+}
+
+pub fn walk_if<F: Fold + ?Sized>(
+    folder: &mut F,
+    cond: Box<Spanned<Expr>>,
+    then: Box<Spanned<Expr>>,
+    otherwise: Box<Spanned<Expr>>,
+    _span: &Span,
+) -> Expr {
+    Expr::If(
+        fold_box(folder, cond),
+        fold_box(folder, then),
+        fold_box(folder, otherwise),
+    )
+}
+
+pub fn walk_while<F: Fold + ?Sized>(
+    folder: &mut F,
+    cond: Box<Spanned<Expr>>,
+    body: Box<Spanned<Expr>>,
+    _span: &Span,
+) -> Expr {
+    Expr::While(fold_box(folder, cond), fold_box(folder, body))
+}
+
+pub fn walk_for<F: Fold + ?Sized>(
+    folder: &mut F,
+    init: Box<Spanned<Expr>>,
+    cond: Box<Spanned<Expr>>,
+    step: Box<Spanned<Expr>>,
+    body: Box<Spanned<Expr>>,
+    _span: &Span,
+) -> Expr {
+    Expr::For(
+        fold_box(folder, init),
+        fold_box(folder, cond),
+        fold_box(folder, step),
+        fold_box(folder, body),
+    )
+}
+
+pub fn walk_return<F: Fold + ?Sized>(folder: &mut F, value: Box<Spanned<Expr>>, _span: &Span) -> Expr {
+    Expr::Return(fold_box(folder, value))
+}
+
+pub fn walk_function<F: Fold + ?Sized>(folder: &mut F, func: Function, _span: &Span) -> Function {
+    Function {
+        params: func
+            .params
+            .into_iter()
+            .map(|(name, ty)| (name, folder.fold_type(ty)))
+            .collect(),
+        ret: folder.fold_type(func.ret),
+        body: func.body.map(|body| fold_box(folder, body)),
+        ..func
+    }
+}
+
+pub fn walk_namespace<F: Fold + ?Sized>(folder: &mut F, namespace: Namespace, _span: &Span) -> Namespace {
+    Namespace {
+        items: fold_list(folder, namespace.items),
+        ..namespace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Folds additions of two literals
+    struct ConstantFold;
+
+    impl Fold for ConstantFold {
+        fn fold_binary(&mut self, op: BinaryOp, lhs: Box<Spanned<Expr>>, rhs: Box<Spanned<Expr>>, span: &Span) -> Expr {
+            match walk_binary(self, op, lhs, rhs, span) {
+                Expr::Add(lhs, rhs) => match (&lhs.1, &rhs.1) {
+                    (Expr::Number(a), Expr::Number(b)) => Expr::Number((a.value + b.value).into()),
+                    _ => Expr::Add(lhs, rhs),
+                },
+                expr => expr,
+            }
+        }
+    }
+
+    #[test]
+    fn fold_rebuilds_bottom_up() {
+        let number = |value: i64, span: Span| Box::new(Spanned(span, Expr::Number(value.into())));
+        // `x = (1 + 2) + 3;`
+        let sum = Spanned(4..15, Expr::Add(number(1, 5..6), number(2, 9..10)));
+        let expr = Spanned(
+            0..19,
+            Expr::Assign(
+                "x".into(),
+                Box::new(Spanned(4..19, Expr::Add(Box::new(sum), number(3, 18..19)))),
+            ),
+        );
+
+        let Spanned(span, Expr::Assign(_, value)) = ConstantFold.fold_expr(expr) else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(span, 0..19);
+        assert_eq!(*value, Spanned(4..19, Expr::Number(6.into())));
+    }
+
+    #[test]
+    fn fold_call_rewrites_the_callee() {
+        struct Qualify;
+
+        impl Fold for Qualify {
+            fn fold_call(&mut self, mut path: Path, args: Vec<Spanned<Expr>>, span: &Span) -> Expr {
+                path.segments.insert(0, "lib".into());
+                walk_call(self, path, args, span)
+            }
+
+            fn fold_ident(&mut self, name: String, _span: &Span) -> Expr {
+                Expr::Ident(name.to_uppercase())
+            }
+        }
+
+        let call = Spanned(0..6, Expr::Call("f".into(), vec![Spanned(2..3, Expr::Ident("x".into()))]));
+        let Spanned(span, Expr::Call(path, args)) = Qualify.fold_expr(call) else {
+            panic!("expected a call");
+        };
+        assert_eq!(span, 0..6);
+        assert_eq!(path.to_string(), "lib.f");
+        assert_eq!(args, [Spanned(2..3, Expr::Ident("X".into()))]);
+    }
+}
@@ -6,20 +6,31 @@
 
 Expr {
     Add/Sub/Mul/Div/Pow [left, right]
-    Neg [term]
+    Eq/NotEq/Greater/Less [left, right]
+    Neg/Not [term]
+    Cast [value, type]
     Decl [name, type, value]
     Assign [name, source]
-    Call [name, args]
+    Call [path, args]
     If [cond, then, else]
     While [cond, body]
     For [init, cond, step, body]
     Parameter [name, type]
-    Function [name, args, body]
+    Function [name, ret, params, body?, doc]
+    Namespace [name, items, doc]
+    InlineAsm [text, outputs, inputs, clobbers]
 }
 */
 
+pub mod arena;
+pub mod fold;
+pub mod visit;
+
+pub use reports::Span;
+
+/// Node tagged with the byte range of the source it was parsed from
 #[derive(Debug, Clone, PartialEq, Hash)]
-pub struct Spanned<T>(pub core::ops::Range<usize>, pub T);
+pub struct Spanned<T>(pub Span, pub T);
 
 impl<T> Spanned<T> {
     pub fn map<B, F>(self, f: F) -> Spanned<B>
@@ -30,39 +41,269 @@ impl<T> Spanned<T> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Expression or statement node, children carry the span they were parsed
+/// from so that every later pass can point at them
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
-    Number(Spanned<i32>),
-    Ident(Spanned<String>),
-    Add(Box<Expr>, Box<Expr>),
-    Sub(Box<Expr>, Box<Expr>),
-    Mul(Box<Expr>, Box<Expr>),
-    Div(Box<Expr>, Box<Expr>),
-    Pow(Box<Expr>, Box<Expr>),
-    Neg(Box<Expr>),
-    Decl(String, Type, Box<Expr>),
-    Assign(String, Box<Expr>),
-    Call(String, Vec<Expr>),
-    Block(Vec<Expr>),
-    If(Box<Expr>, Box<Expr>, Box<Expr>),
-    While(Box<Expr>, Box<Expr>),
-    For(Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>),
-    Return(Box<Expr>),
+    Number(NumberLiteral),
+    Char(u8),
+    /// Bytes of the literal, so `\xNN` escapes may fill lookup tables
+    Str(Vec<u8>),
+    Ident(String),
+    Add(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Sub(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Mul(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Div(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Pow(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Eq(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    NotEq(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Greater(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Less(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Neg(Box<Spanned<Expr>>),
+    Not(Box<Spanned<Expr>>),
+    /// `value as type`
+    Cast(Box<Spanned<Expr>>, Spanned<Type>),
+    Decl(String, Spanned<Type>, Box<Spanned<Expr>>),
+    Assign(String, Box<Spanned<Expr>>),
+    Call(Path, Vec<Spanned<Expr>>),
+    Block(Vec<Spanned<Expr>>),
+    If(Box<Spanned<Expr>>, Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    While(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    For(Box<Spanned<Expr>>, Box<Spanned<Expr>>, Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Return(Box<Spanned<Expr>>),
     Break,
     Continue,
-    Function(String, Type, Vec<(String, String)>, Box<Expr>),
+    Function(Function),
+    Namespace(Namespace),
+    InlineAsm(InlineAsm),
     Empty,
 }
 
-#[derive(Debug, Clone)]
+/// `name:type`, both spanned so reports can point at either
+pub type Parameter = (Spanned<String>, Spanned<Type>);
+
+/// `func name(params) > ret { body }`, or a prototype `func name(params) >
+/// ret;` of a function defined further down or outside of the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub ret: Spanned<Type>,
+    pub params: Vec<Parameter>,
+    /// `None` for prototypes
+    pub body: Option<Box<Spanned<Expr>>>,
+    /// `///` lines right above the definition
+    pub doc: Option<String>,
+}
+
+/// `namespace name { items }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    pub name: String,
+    pub items: Vec<Spanned<Expr>>,
+    pub doc: Option<String>,
+}
+
+/// Integer literal along with its source text, as in `0x1F` or `1_000`,
+/// which range checks against the type it is used as can point at
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberLiteral {
+    pub value: i64,
+    pub text: String,
+}
+
+impl From<i64> for NumberLiteral {
+    fn from(value: i64) -> Self {
+        Self {
+            value,
+            text: value.to_string(),
+        }
+    }
+}
+
+/// Possibly qualified function name, as in `tst`, `LSpace.tst` or `here.tst`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    /// The path is relative to the enclosing namespace only (`here.`)
+    pub here: bool,
+    pub segments: Vec<String>,
+}
+
+impl Path {
+    pub fn name(&self) -> &str {
+        self.segments.last().map_or("", String::as_str)
+    }
+}
+
+impl From<&str> for Path {
+    fn from(value: &str) -> Self {
+        Self {
+            here: false,
+            segments: vec![value.into()],
+        }
+    }
+}
+
+impl core::fmt::Display for Path {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.here {
+            f.write_str("here.")?;
+        }
+        f.write_str(&self.segments.join("."))
+    }
+}
+
+/// Binding of a variable to an inline asm register, as in `"=r"(x)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmOperand {
+    pub constraint: String,
+    pub variable: String,
+}
+
+/// `asm (outputs : inputs : clobbers) { text }`, operands are referred to
+/// as `%0`, `%1`, ... in the text, outputs first. Without operands the text
+/// is spliced verbatim into the enclosing function
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InlineAsm {
+    pub text: String,
+    pub outputs: Vec<AsmOperand>,
+    pub inputs: Vec<AsmOperand>,
+    pub clobbers: Vec<String>,
+}
+
+impl InlineAsm {
+    pub fn has_operands(&self) -> bool {
+        !(self.outputs.is_empty() && self.inputs.is_empty() && self.clobbers.is_empty())
+    }
+}
+
+/// Type annotation, `int`, `char` and `long` are spelled `i16`, `u8` and
+/// `i32` here
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
-    Int,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    Bool,
+    Void,
     Float,
+    /// `*T`
+    Pointer(Box<Type>),
+    /// `[T; N]`
+    Array(Box<Type>, usize),
+    /// Struct referred to by name
+    Named(String),
+}
+
+impl Type {
+    /// Builtin type spelled `name`, including the C-like aliases
+    pub fn builtin(name: &str) -> Option<Type> {
+        Some(match name {
+            "u8" | "char" => Type::U8,
+            "i8" => Type::I8,
+            "u16" => Type::U16,
+            "i16" | "int" => Type::I16,
+            "u32" => Type::U32,
+            "i32" | "long" => Type::I32,
+            "bool" => Type::Bool,
+            "void" => Type::Void,
+            "float" => Type::Float,
+            _ => return None,
+        })
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::U8 | Type::I8 | Type::U16 | Type::I16 | Type::U32 | Type::I32)
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32)
+    }
 
-    Other(String),
+    /// Width of an integer type in bytes
+    pub fn width(&self) -> Option<u8> {
+        match self {
+            Type::U8 | Type::I8 => Some(1),
+            Type::U16 | Type::I16 => Some(2),
+            Type::U32 | Type::I32 => Some(4),
+            _ => None,
+        }
+    }
+
+    fn integer(width: u8, signed: bool) -> Type {
+        match (width, signed) {
+            (1, false) => Type::U8,
+            (1, true) => Type::I8,
+            (2, false) => Type::U16,
+            (2, true) => Type::I16,
+            (4, false) => Type::U32,
+            _ => Type::I32,
+        }
+    }
+
+    /// Type both operands of an arithmetic operator are converted to. The
+    /// wider operand wins, between a signed and an unsigned operand of the
+    /// same width the unsigned one does, as in C
+    pub fn promote(&self, other: &Type) -> Option<Type> {
+        let (lhs, rhs) = (self.width()?, other.width()?);
+        Some(match self.is_signed() == other.is_signed() {
+            true => Type::integer(lhs.max(rhs), self.is_signed()),
+            false => {
+                let (signed, unsigned) = if self.is_signed() { (lhs, rhs) } else { (rhs, lhs) };
+                Type::integer(signed.max(unsigned), signed > unsigned)
+            }
+        })
+    }
+
+    /// Whether a value of this type converts to `target` without an `as`,
+    /// which is the case when every value is kept
+    pub fn widens_to(&self, target: &Type) -> bool {
+        if self == target {
+            return true;
+        }
+        match (self.width(), target.width()) {
+            (Some(from), Some(to)) => match (self.is_signed(), target.is_signed()) {
+                (false, true) => from < to,
+                (true, false) => false,
+                _ => from <= to,
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether `value as target` is allowed. Integers, bools and pointers
+    /// convert to integers, pointers convert to pointers and `u16`
+    pub fn casts_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Bool, to) | (Type::Pointer(_), to) if to.is_integer() => true,
+            (Type::Pointer(_) | Type::U16, Type::Pointer(_)) => true,
+            (from, to) => from.is_integer() && to.is_integer(),
+        }
+    }
+}
+
+impl core::fmt::Display for Type {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Type::U8 => f.write_str("u8"),
+            Type::I8 => f.write_str("i8"),
+            Type::U16 => f.write_str("u16"),
+            Type::I16 => f.write_str("i16"),
+            Type::U32 => f.write_str("u32"),
+            Type::I32 => f.write_str("i32"),
+            Type::Bool => f.write_str("bool"),
+            Type::Void => f.write_str("void"),
+            Type::Float => f.write_str("float"),
+            Type::Pointer(inner) => write!(f, "*{inner}"),
+            Type::Array(element, len) => write!(f, "[{element}; {len}]"),
+            Type::Named(name) => f.write_str(name),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Ast {
-    pub root: Vec<Expr>,
+    pub root: Vec<Spanned<Expr>>,
 }
@@ -0,0 +1,558 @@
+//! Read-only and in-place traversals, one hook per kind of node. Hooks of
+//! nodes with children default to the `walk_*` function that visits them,
+//! hooks of leaves do nothing
+
+use crate::{
+    arena::{BinaryOp, UnaryOp},
+    Ast, Expr, Function, InlineAsm, Namespace, NumberLiteral, Path, Span, Spanned, Type,
+};
+
+pub trait Visitor {
+    fn visit_ast(&mut self, ast: &Ast) {
+        walk_ast(self, ast)
+    }
+
+    fn visit_expr(&mut self, expr: &Spanned<Expr>) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_number(&mut self, _number: &NumberLiteral, _span: &Span) {}
+
+    fn visit_char(&mut self, _value: u8, _span: &Span) {}
+
+    fn visit_str(&mut self, _value: &[u8], _span: &Span) {}
+
+    fn visit_ident(&mut self, _name: &str, _span: &Span) {}
+
+    fn visit_binary(&mut self, op: BinaryOp, lhs: &Spanned<Expr>, rhs: &Spanned<Expr>, span: &Span) {
+        walk_binary(self, op, lhs, rhs, span)
+    }
+
+    fn visit_unary(&mut self, op: UnaryOp, term: &Spanned<Expr>, span: &Span) {
+        walk_unary(self, op, term, span)
+    }
+
+    fn visit_cast(&mut self, value: &Spanned<Expr>, ty: &Spanned<Type>, span: &Span) {
+        walk_cast(self, value, ty, span)
+    }
+
+    fn visit_decl(&mut self, name: &str, ty: &Spanned<Type>, value: &Spanned<Expr>, span: &Span) {
+        walk_decl(self, name, ty, value, span)
+    }
+
+    fn visit_assign(&mut self, name: &str, value: &Spanned<Expr>, span: &Span) {
+        walk_assign(self, name, value, span)
+    }
+
+    fn visit_call(&mut self, path: &Path, args: &[Spanned<Expr>], span: &Span) {
+        walk_call(self, path, args, span)
+    }
+
+    fn visit_block(&mut self, stats: &[Spanned<Expr>], span: &Span) {
+        walk_block(self, stats, span)
+    }
+
+    fn visit_if(&mut self, cond: &Spanned<Expr>, then: &Spanned<Expr>, otherwise: &Spanned<Expr>, span: &Span) {
+        walk_if(self, cond, then, otherwise, span)
+    }
+
+    fn visit_while(&mut self, cond: &Spanned<Expr>, body: &Spanned<Expr>, span: &Span) {
+        walk_while(self, cond, body, span)
+    }
+
+    fn visit_for(
+        &mut self,
+        init: &Spanned<Expr>,
+        cond: &Spanned<Expr>,
+        step: &Spanned<Expr>,
+        body: &Spanned<Expr>,
+        span: &Span,
+    ) {
+        walk_for(self, init, cond, step, body, span)
+    }
+
+    fn visit_return(&mut self, value: &Spanned<Expr>, span: &Span) {
+        walk_return(self, value, span)
+    }
+
+    fn visit_function(&mut self, func: &Function, span: &Span) {
+        walk_function(self, func, span)
+    }
+
+    fn visit_namespace(&mut self, namespace: &Namespace, span: &Span) {
+        walk_namespace(self, namespace, span)
+    }
+
+    fn visit_type(&mut self, _ty: &Spanned<Type>) {}
+
+    fn visit_inline_asm(&mut self, _asm: &InlineAsm, _span: &Span) {}
+}
+
+pub fn walk_ast<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast) {
+    for item in &ast.root {
+        visitor.visit_expr(item);
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Spanned<Expr>) {
+    let Spanned(span, expr) = expr;
+    match expr {
+        Expr::Number(number) => visitor.visit_number(number, span),
+        Expr::Char(value) => visitor.visit_char(*value, span),
+        Expr::Str(value) => visitor.visit_str(value, span),
+        Expr::Ident(name) => visitor.visit_ident(name, span),
+        Expr::Add(lhs, rhs) => visitor.visit_binary(BinaryOp::Add, lhs, rhs, span),
+        Expr::Sub(lhs, rhs) => visitor.visit_binary(BinaryOp::Sub, lhs, rhs, span),
+        Expr::Mul(lhs, rhs) => visitor.visit_binary(BinaryOp::Mul, lhs, rhs, span),
+        Expr::Div(lhs, rhs) => visitor.visit_binary(BinaryOp::Div, lhs, rhs, span),
+        Expr::Pow(lhs, rhs) => visitor.visit_binary(BinaryOp::Pow, lhs, rhs, span),
+        Expr::Eq(lhs, rhs) => visitor.visit_binary(BinaryOp::Eq, lhs, rhs, span),
+        Expr::NotEq(lhs, rhs) => visitor.visit_binary(BinaryOp::NotEq, lhs, rhs, span),
+        Expr::Greater(lhs, rhs) => visitor.visit_binary(BinaryOp::Greater, lhs, rhs, span),
+        Expr::Less(lhs, rhs) => visitor.visit_binary(BinaryOp::Less, lhs, rhs, span),
+        Expr::Neg(term) => visitor.visit_unary(UnaryOp::Neg, term, span),
+        Expr::Not(term) => visitor.visit_unary(UnaryOp::Not, term, span),
+        Expr::Cast(value, ty) => visitor.visit_cast(value, ty, span),
+        Expr::Decl(name, ty, value) => visitor.visit_decl(name, ty, value, span),
+        Expr::Assign(name, value) => visitor.visit_assign(name, value, span),
+        Expr::Call(path, args) => visitor.visit_call(path, args, span),
+        Expr::Block(stats) => visitor.visit_block(stats, span),
+        Expr::If(cond, then, otherwise) => visitor.visit_if(cond, then, otherwise, span),
+        Expr::While(cond, body) => visitor.visit_while(cond, body, span),
+        Expr::For(init, cond, step, body) => visitor.visit_for(init, cond, step, body, span),
+        Expr::Return(value) => visitor.visit_return(value, span),
+        Expr::Function(func) => visitor.visit_function(func, span),
+        Expr::Namespace(namespace) => visitor.visit_namespace(namespace, span),
+        Expr::InlineAsm(asm) => visitor.visit_inline_asm(asm, span),
+        Expr::Break | Expr::Continue | Expr::Empty => {}
+    }
+}
+
+pub fn walk_binary<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    _op: BinaryOp,
+    lhs: &Spanned<Expr>,
+    rhs: &Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr(lhs);
+    visitor.visit_expr(rhs);
+}
+
+pub fn walk_unary<V: Visitor + ?Sized>(visitor: &mut V, _op: UnaryOp, term: &Spanned<Expr>, _span: &Span) {
+    visitor.visit_expr(term);
+}
+
+pub fn walk_cast<V: Visitor + ?Sized>(visitor: &mut V, value: &Spanned<Expr>, ty: &Spanned<Type>, _span: &Span) {
+    visitor.visit_expr(value);
+    visitor.visit_type(ty);
+}
+
+pub fn walk_decl<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    _name: &str,
+    ty: &Spanned<Type>,
+    value: &Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_type(ty);
+    visitor.visit_expr(value);
+}
+
+pub fn walk_assign<V: Visitor + ?Sized>(visitor: &mut V, _name: &str, value: &Spanned<Expr>, _span: &Span) {
+    visitor.visit_expr(value);
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, _path: &Path, args: &[Spanned<Expr>], _span: &Span) {
+    for arg in args {
+        visitor.visit_expr(arg);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, stats: &[Spanned<Expr>], _span: &Span) {
+    for stat in stats {
+        visitor.visit_expr(stat);
+    }
+}
+
+pub fn walk_if<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    cond: &Spanned<Expr>,
+    then: &Spanned<Expr>,
+    otherwise: &Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr(cond);
+    visitor.visit_expr(then);
+    visitor.visit_expr(otherwise);
+}
+
+pub fn walk_while<V: Visitor + ?Sized>(visitor: &mut V, cond: &Spanned<Expr>, body: &Spanned<Expr>, _span: &Span) {
+    visitor.visit_expr(cond);
+    visitor.visit_expr(body);
+}
+
+pub fn walk_for<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    init: &Spanned<Expr>,
+    cond: &Spanned<Expr>,
+    step: &Spanned<Expr>,
+    body: &Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr(init);
+    visitor.visit_expr(cond);
+    visitor.visit_expr(step);
+    visitor.visit_expr(body);
+}
+
+pub fn walk_return<V: Visitor + ?Sized>(visitor: &mut V, value: &Spanned<Expr>, _span: &Span) {
+    visitor.visit_expr(value);
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, func: &Function, _span: &Span) {
+    for (_, ty) in &func.params {
+        visitor.visit_type(ty);
+    }
+    visitor.visit_type(&func.ret);
+    if let Some(body) = &func.body {
+        visitor.visit_expr(body);
+    }
+}
+
+pub fn walk_namespace<V: Visitor + ?Sized>(visitor: &mut V, namespace: &Namespace, _span: &Span) {
+    for item in &namespace.items {
+        visitor.visit_expr(item);
+    }
+}
+
+pub trait VisitorMut {
+    fn visit_ast_mut(&mut self, ast: &mut Ast) {
+        walk_ast_mut(self, ast)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Spanned<Expr>) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_number_mut(&mut self, _number: &mut NumberLiteral, _span: &Span) {}
+
+    fn visit_char_mut(&mut self, _value: &mut u8, _span: &Span) {}
+
+    fn visit_str_mut(&mut self, _value: &mut Vec<u8>, _span: &Span) {}
+
+    fn visit_ident_mut(&mut self, _name: &mut String, _span: &Span) {}
+
+    fn visit_binary_mut(&mut self, op: BinaryOp, lhs: &mut Spanned<Expr>, rhs: &mut Spanned<Expr>, span: &Span) {
+        walk_binary_mut(self, op, lhs, rhs, span)
+    }
+
+    fn visit_unary_mut(&mut self, op: UnaryOp, term: &mut Spanned<Expr>, span: &Span) {
+        walk_unary_mut(self, op, term, span)
+    }
+
+    fn visit_cast_mut(&mut self, value: &mut Spanned<Expr>, ty: &mut Spanned<Type>, span: &Span) {
+        walk_cast_mut(self, value, ty, span)
+    }
+
+    fn visit_decl_mut(&mut self, name: &mut String, ty: &mut Spanned<Type>, value: &mut Spanned<Expr>, span: &Span) {
+        walk_decl_mut(self, name, ty, value, span)
+    }
+
+    fn visit_assign_mut(&mut self, name: &mut String, value: &mut Spanned<Expr>, span: &Span) {
+        walk_assign_mut(self, name, value, span)
+    }
+
+    fn visit_call_mut(&mut self, path: &mut Path, args: &mut [Spanned<Expr>], span: &Span) {
+        walk_call_mut(self, path, args, span)
+    }
+
+    fn visit_block_mut(&mut self, stats: &mut [Spanned<Expr>], span: &Span) {
+        walk_block_mut(self, stats, span)
+    }
+
+    fn visit_if_mut(
+        &mut self,
+        cond: &mut Spanned<Expr>,
+        then: &mut Spanned<Expr>,
+        otherwise: &mut Spanned<Expr>,
+        span: &Span,
+    ) {
+        walk_if_mut(self, cond, then, otherwise, span)
+    }
+
+    fn visit_while_mut(&mut self, cond: &mut Spanned<Expr>, body: &mut Spanned<Expr>, span: &Span) {
+        walk_while_mut(self, cond, body, span)
+    }
+
+    fn visit_for_mut(
+        &mut self,
+        init: &mut Spanned<Expr>,
+        cond: &mut Spanned<Expr>,
+        step: &mut Spanned<Expr>,
+        body: &mut Spanned<Expr>,
+        span: &Span,
+    ) {
+        walk_for_mut(self, init, cond, step, body, span)
+    }
+
+    fn visit_return_mut(&mut self, value: &mut Spanned<Expr>, span: &Span) {
+        walk_return_mut(self, value, span)
+    }
+
+    fn visit_function_mut(&mut self, func: &mut Function, span: &Span) {
+        walk_function_mut(self, func, span)
+    }
+
+    fn visit_namespace_mut(&mut self, namespace: &mut Namespace, span: &Span) {
+        walk_namespace_mut(self, namespace, span)
+    }
+
+    fn visit_type_mut(&mut self, _ty: &mut Spanned<Type>) {}
+
+    fn visit_inline_asm_mut(&mut self, _asm: &mut InlineAsm, _span: &Span) {}
+}
+
+pub fn walk_ast_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut Ast) {
+    for item in &mut ast.root {
+        visitor.visit_expr_mut(item);
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Spanned<Expr>) {
+    let Spanned(span, expr) = expr;
+    match expr {
+        Expr::Number(number) => visitor.visit_number_mut(number, span),
+        Expr::Char(value) => visitor.visit_char_mut(value, span),
+        Expr::Str(value) => visitor.visit_str_mut(value, span),
+        Expr::Ident(name) => visitor.visit_ident_mut(name, span),
+        Expr::Add(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Add, lhs, rhs, span),
+        Expr::Sub(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Sub, lhs, rhs, span),
+        Expr::Mul(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Mul, lhs, rhs, span),
+        Expr::Div(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Div, lhs, rhs, span),
+        Expr::Pow(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Pow, lhs, rhs, span),
+        Expr::Eq(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Eq, lhs, rhs, span),
+        Expr::NotEq(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::NotEq, lhs, rhs, span),
+        Expr::Greater(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Greater, lhs, rhs, span),
+        Expr::Less(lhs, rhs) => visitor.visit_binary_mut(BinaryOp::Less, lhs, rhs, span),
+        Expr::Neg(term) => visitor.visit_unary_mut(UnaryOp::Neg, term, span),
+        Expr::Not(term) => visitor.visit_unary_mut(UnaryOp::Not, term, span),
+        Expr::Cast(value, ty) => visitor.visit_cast_mut(value, ty, span),
+        Expr::Decl(name, ty, value) => visitor.visit_decl_mut(name, ty, value, span),
+        Expr::Assign(name, value) => visitor.visit_assign_mut(name, value, span),
+        Expr::Call(path, args) => visitor.visit_call_mut(path, args, span),
+        Expr::Block(stats) => visitor.visit_block_mut(stats, span),
+        Expr::If(cond, then, otherwise) => visitor.visit_if_mut(cond, then, otherwise, span),
+        Expr::While(cond, body) => visitor.visit_while_mut(cond, body, span),
+        Expr::For(init, cond, step, body) => visitor.visit_for_mut(init, cond, step, body, span),
+        Expr::Return(value) => visitor.visit_return_mut(value, span),
+        Expr::Function(func) => visitor.visit_function_mut(func, span),
+        Expr::Namespace(namespace) => visitor.visit_namespace_mut(namespace, span),
+        Expr::InlineAsm(asm) => visitor.visit_inline_asm_mut(asm, span),
+        Expr::Break | Expr::Continue | Expr::Empty => {}
+    }
+}
+
+pub fn walk_binary_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    _op: BinaryOp,
+    lhs: &mut Spanned<Expr>,
+    rhs: &mut Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr_mut(lhs);
+    visitor.visit_expr_mut(rhs);
+}
+
+pub fn walk_unary_mut<V: VisitorMut + ?Sized>(visitor: &mut V, _op: UnaryOp, term: &mut Spanned<Expr>, _span: &Span) {
+    visitor.visit_expr_mut(term);
+}
+
+pub fn walk_cast_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    value: &mut Spanned<Expr>,
+    ty: &mut Spanned<Type>,
+    _span: &Span,
+) {
+    visitor.visit_expr_mut(value);
+    visitor.visit_type_mut(ty);
+}
+
+pub fn walk_decl_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    _name: &mut String,
+    ty: &mut Spanned<Type>,
+    value: &mut Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_type_mut(ty);
+    visitor.visit_expr_mut(value);
+}
+
+pub fn walk_assign_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    _name: &mut String,
+    value: &mut Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr_mut(value);
+}
+
+pub fn walk_call_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    _path: &mut Path,
+    args: &mut [Spanned<Expr>],
+    _span: &Span,
+) {
+    for arg in args {
+        visitor.visit_expr_mut(arg);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stats: &mut [Spanned<Expr>], _span: &Span) {
+    for stat in stats {
+        visitor.visit_expr_mut(stat);
+    }
+}
+
+pub fn walk_if_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    cond: &mut Spanned<Expr>,
+    then: &mut Spanned<Expr>,
+    otherwise: &mut Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr_mut(cond);
+    visitor.visit_expr_mut(then);
+    visitor.visit_expr_mut(otherwise);
+}
+
+pub fn walk_while_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    cond: &mut Spanned<Expr>,
+    body: &mut Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr_mut(cond);
+    visitor.visit_expr_mut(body);
+}
+
+pub fn walk_for_mut<V: VisitorMut + ?Sized>(
+    visitor: &mut V,
+    init: &mut Spanned<Expr>,
+    cond: &mut Spanned<Expr>,
+    step: &mut Spanned<Expr>,
+    body: &mut Spanned<Expr>,
+    _span: &Span,
+) {
+    visitor.visit_expr_mut(init);
+    visitor.visit_expr_mut(cond);
+    visitor.visit_expr_mut(step);
+    visitor.visit_expr_mut(body);
+}
+
+pub fn walk_return_mut<V: VisitorMut + ?Sized>(visitor: &mut V, value: &mut Spanned<Expr>, _span: &Span) {
+    visitor.visit_expr_mut(value);
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func: &mut Function, _span: &Span) {
+    for (_, ty) in &mut func.params {
+        visitor.visit_type_mut(ty);
+    }
+    visitor.visit_type_mut(&mut func.ret);
+    if let Some(body) = &mut func.body {
+        visitor.visit_expr_mut(body);
+    }
+}
+
+pub fn walk_namespace_mut<V: VisitorMut + ?Sized>(visitor: &mut V, namespace: &mut Namespace, _span: &Span) {
+    for item in &mut namespace.items {
+        visitor.visit_expr_mut(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(expr: Expr) -> Spanned<Expr> {
+        Spanned(0..0, expr)
+    }
+
+    /// `func f(a:i16) > u8 { g(a, b); return a; }`
+    fn sample() -> Ast {
+        let ident = |name: &str| node(Expr::Ident(name.into()));
+        let body = Expr::Block(vec![
+            node(Expr::Call("g".into(), vec![ident("a"), ident("b")])),
+            node(Expr::Return(Box::new(ident("a")))),
+        ]);
+        Ast {
+            root: vec![node(Expr::Function(Function {
+                name: "f".into(),
+                ret: Spanned(0..0, Type::U8),
+                params: vec![(Spanned(0..0, "a".into()), Spanned(0..0, Type::I16))],
+                body: Some(Box::new(node(body))),
+                doc: None,
+            }))],
+        }
+    }
+
+    #[test]
+    fn visitor_reaches_every_node() {
+        #[derive(Default)]
+        struct Collect {
+            idents: Vec<String>,
+            calls: Vec<String>,
+            types: Vec<Type>,
+            returns: usize,
+        }
+
+        impl Visitor for Collect {
+            fn visit_ident(&mut self, name: &str, _span: &Span) {
+                self.idents.push(name.into());
+            }
+
+            fn visit_return(&mut self, value: &Spanned<Expr>, span: &Span) {
+                self.returns += 1;
+                walk_return(self, value, span)
+            }
+
+            fn visit_call(&mut self, path: &Path, args: &[Spanned<Expr>], span: &Span) {
+                self.calls.push(path.to_string());
+                walk_call(self, path, args, span)
+            }
+
+            fn visit_type(&mut self, ty: &Spanned<Type>) {
+                self.types.push(ty.1.clone());
+            }
+        }
+
+        let mut collect = Collect::default();
+        collect.visit_ast(&sample());
+        assert_eq!(collect.idents, ["a", "b", "a"]);
+        assert_eq!(collect.calls, ["g"]);
+        assert_eq!(collect.types, [Type::I16, Type::U8]);
+        assert_eq!(collect.returns, 1);
+    }
+
+    #[test]
+    fn visitor_mut_rewrites_in_place() {
+        struct Rename;
+
+        impl VisitorMut for Rename {
+            fn visit_ident_mut(&mut self, name: &mut String, _span: &Span) {
+                name.push('_');
+            }
+        }
+
+        let mut ast = sample();
+        Rename.visit_ast_mut(&mut ast);
+        let Expr::Function(func) = &ast.root[0].1 else {
+            unreachable!()
+        };
+        let Some(Spanned(_, Expr::Block(stats))) = func.body.as_deref() else {
+            unreachable!()
+        };
+        assert!(matches!(&stats[1].1, Expr::Return(value) if matches!(&value.1, Expr::Ident(name) if name == "a_")));
+    }
+}
@@ -145,6 +145,11 @@ impl AVRWriter {
         self.label = label as usize;
     }
 
+    /// Currently selected section and label
+    pub fn position(&self) -> (u16, u16) {
+        (self.section as u16, self.label as u16)
+    }
+
     pub fn new_global(&mut self, name: &str) {
         self.globals.push(name.to_string());
     }
@@ -194,6 +199,16 @@ impl AVRWriter {
         self.append_instruction(format!("ldi {:?}, {}", register, value));
     }
 
+    pub fn rcall(&mut self, label: &str) {
+        self.append_instruction(format!("rcall {}", label));
+    }
+
+    /// Loads the low (`lo8`) or high (`hi8`) byte of a label's address
+    pub fn ldi_label(&mut self, register: Registers, label: &str, high: bool) {
+        let part = if high { "hi8" } else { "lo8" };
+        self.append_instruction(format!("ldi {:?}, {}({})", register, part, label));
+    }
+
     pub fn ret(&mut self) {
         self.append_instruction("ret".to_string());
     }
@@ -218,6 +233,22 @@ impl AVRWriter {
         self.append_instruction(format!("adc {:?}, {:?}", dest, source));
     }
 
+    pub fn sub(&mut self, dest: Registers, source: Registers) {
+        self.append_instruction(format!("sub {:?}, {:?}", dest, source));
+    }
+
+    pub fn sbc(&mut self, dest: Registers, source: Registers) {
+        self.append_instruction(format!("sbc {:?}, {:?}", dest, source));
+    }
+
+    pub fn lsl(&mut self, register: Registers) {
+        self.append_instruction(format!("lsl {:?}", register));
+    }
+
+    pub fn clr(&mut self, register: Registers) {
+        self.append_instruction(format!("clr {:?}", register));
+    }
+
     pub fn mov(&mut self, dest: Registers, source: Registers) {
         self.append_instruction(format!("mov {:?}, {:?}", dest, source));
     }
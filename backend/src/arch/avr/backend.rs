@@ -1,90 +1,220 @@
 use crate::arch::avr::asm_writer::*;
 
-use ast::{Ast, Expr, Spanned, Type};
+use ast::{
+    arena::{Arena, BinaryOp, ExprId, Item, ItemId, Node, SideTable},
+    Ast, InlineAsm, Span, Spanned, Type,
+};
+use reports::{sourcemap::SourceKey, IntoReport, Level, Location, Report, ReportContext};
+use sema::{
+    resolve::{Resolution, Resolver, SymbolId},
+    typeck::TypeChecker,
+};
 
 const R24: u32 = 1 << 2; // R24 - R27
 const R18: u32 = 2 << 2; // R18 - R23
 const R16: u32 = 4 << 2; // R16 - R18  LIMITED TO SHORT INT FOR SMALL OPS
 const EMPTY: u32 = 0 << 2; // No register
 
-pub enum BackendError {
+/// Allocator slots with their first register and size in bytes
+const SLOTS: [(u32, Registers, u16); 3] = [
+    (R24, Registers::R24, 4),
+    (R18, Registers::R18, 6),
+    (R16, Registers::R16, 2),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackendErrorKind {
     AssemblerError,
     RanOutOfRegisters,
     UnsupportedBinaryOperation,
     UnsupportedValue,
     CannotResolveFunction,
+    UnsupportedConstraint,
+    UnsupportedType,
+    DuplicateFunction,
+    ArgumentCountMismatch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendError {
+    pub kind: BackendErrorKind,
+    pub location: Location,
+}
+
+impl IntoReport for BackendError {
+    fn into_report(self) -> Report {
+        let title = match self.kind {
+            BackendErrorKind::AssemblerError => "cannot generate code for this",
+            BackendErrorKind::RanOutOfRegisters => "ran out of registers",
+            BackendErrorKind::UnsupportedBinaryOperation => "unsupported binary operation",
+            BackendErrorKind::UnsupportedValue => "unsupported value",
+            BackendErrorKind::CannotResolveFunction => "cannot resolve function",
+            BackendErrorKind::UnsupportedConstraint => "unsupported asm constraint",
+            BackendErrorKind::UnsupportedType => "unsupported type",
+            BackendErrorKind::DuplicateFunction => "function is defined multiple times",
+            BackendErrorKind::ArgumentCountMismatch => "wrong number of arguments",
+        };
+        Report::new(
+            Level::Error,
+            self.location.span().clone(),
+            self.location.source_key(),
+            title,
+            None::<String>,
+        )
+    }
 }
 
 struct Function {
+    /// Mangled assembler label, namespaces joined with `.`
     name: String,
-    ret: Type,
-    args: Vec<String>,
-    address: u16,
+    ret: Spanned<Type>,
+    args: Vec<Type>,
+    /// Label of the definition, `None` while only a prototype is known,
+    /// calls then go to a symbol defined outside of the program
+    address: Option<u16>,
 }
 
 #[derive(Clone)]
 struct Variable {
-    name: String,
+    ty: Type,
     size: u16,
     stack_offset: u16,
 }
 
 struct Context {
-    functions: Vec<Function>,
-    locals: Vec<Variable>,
+    /// Keyed by the symbol shared by a function and its prototypes
+    functions: SideTable<SymbolId, Function>,
+    namespace: Vec<String>,
+    /// Keyed by the symbol of their `Decl`
+    locals: SideTable<SymbolId, Variable>,
     text: u16,
     data: u16,
+    strings: u16,
     stack_offset: u16,
     used_regs: u32,
     target_register: Registers,
+    /// First register of the value emitted last
+    result: Registers,
+    /// Return type of the function being emitted
+    ret: Type,
 }
 
 pub struct AVRBackend<'a> {
-    nodes: &'a [Expr],
+    arena: &'a Arena,
+    resolution: &'a Resolution,
+    source_key: SourceKey,
     assm: AVRWriter,
     ctx: Context,
 }
 
 impl<'a> AVRBackend<'a> {
-    pub fn new(source: &'a [Expr]) -> Self {
+    /// Names are looked up in `resolution`, which has to come from the
+    /// same `arena`
+    pub fn new(arena: &'a Arena, resolution: &'a Resolution, source_key: SourceKey) -> Self {
         AVRBackend {
-            nodes: source,
+            arena,
+            resolution,
+            source_key,
             assm: AVRWriter::new(),
             ctx: Context {
-                functions: Vec::new(),
-                locals: Vec::new(),
+                functions: SideTable::new(),
+                namespace: Vec::new(),
+                locals: SideTable::new(),
                 text: 0,
                 data: 0,
+                strings: 0,
                 stack_offset: 0,
                 used_regs: EMPTY,
                 target_register: Registers::R0,
+                result: Registers::R0,
+                ret: Type::Void,
             },
         }
     }
 
-    fn emit_function(
-        &mut self,
-        name: &str,
-        ret: &Type,
-        args: &[(String, String)],
-        body: &Expr,
-    ) -> Result<(), BackendError> {
-        let addr = self.assm.create_label(name);
+    fn error(&self, kind: BackendErrorKind, span: &Span) -> BackendError {
+        BackendError {
+            kind,
+            location: Location::new(span.clone(), self.source_key),
+        }
+    }
 
-        self.ctx.functions.push(Function {
-            name: name.into(),
-            ret: ret.clone(),
-            args: args.iter().map(|(_, ty)| ty.clone()).collect(),
-            address: addr,
-        });
+    /// Label of `name` declared in the current namespace, soel identifiers
+    /// cannot contain `.` so mangled names never collide
+    fn mangle(&self, name: &str) -> String {
+        self.ctx
+            .namespace
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(name))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Registers every function of `items` and the namespaces among them
+    /// before anything is emitted, so calls do not depend on the order of
+    /// definitions. Labels are created in source order, a second definition
+    /// under the same label is rejected
+    fn declare_functions(&mut self, items: &[ItemId]) -> Result<(), BackendError> {
+        let arena = self.arena;
+        for &item in items {
+            match &arena[item] {
+                Item::Function { name, ret, params, body, .. } => {
+                    let Some(&symbol) = self.resolution.items.get(item) else {
+                        continue;
+                    };
+                    let label = self.mangle(name);
+                    let defined = |func: &Function| func.name == label && func.address.is_some();
+                    if body.is_some() && self.ctx.functions.values().any(defined) {
+                        return Err(self.error(BackendErrorKind::DuplicateFunction, &arena.items[item].0));
+                    }
+                    let address = body.map(|_| self.assm.create_label(&label));
+                    match self.ctx.functions.get_mut(symbol) {
+                        Some(known) => known.address = known.address.or(address),
+                        None => {
+                            self.ctx.functions.insert(
+                                symbol,
+                                Function {
+                                    name: label,
+                                    ret: ret.clone(),
+                                    args: params.iter().map(|(_, ty)| ty.1.clone()).collect(),
+                                    address,
+                                },
+                            );
+                        }
+                    }
+                }
+                Item::Namespace { name, items, .. } => {
+                    self.ctx.namespace.push(name.clone());
+                    self.declare_functions(items)?;
+                    self.ctx.namespace.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_function(&mut self, item: ItemId, ret: &Spanned<Type>, body: ExprId) -> Result<(), BackendError> {
+        let addr = self
+            .resolution
+            .items
+            .get(item)
+            .and_then(|symbol| self.ctx.functions.get(*symbol))
+            .and_then(|func| func.address)
+            .expect("definitions are declared before emitting");
         self.assm.select_label(addr);
 
+        // Locals of the previous function are out of scope
+        self.ctx.locals.clear();
+        self.ctx.stack_offset = 0;
+        self.ctx.ret = ret.1.clone();
+
         self.assm.function_prologue();
 
-        match body {
-            Expr::Block(stats) => {
+        match &self.arena[body] {
+            Node::Block(stats) => {
                 for stat in stats {
-                    self.emit_statement(stat)?;
+                    self.emit_statement(*stat)?;
                 }
             }
             _ => {
@@ -92,12 +222,13 @@ impl<'a> AVRBackend<'a> {
             }
         }
 
-        for _ in 0..self.ctx.stack_offset {
+        // Every `rcall .+0` reserves two bytes of the frame
+        let frame = self.ctx.stack_offset.div_ceil(2);
+        for _ in 0..2 * frame {
             self.assm.pop(Registers::R0);
         }
 
-        // Fix sync problems by adding 1*locals clock cycles
-        for i in 0..self.ctx.locals.len() {
+        for i in 0..frame as usize {
             self.assm.append_after("rcall .+0".to_string(), 1 + i);
         }
 
@@ -121,16 +252,69 @@ impl<'a> AVRBackend<'a> {
         }
     }
 
-    fn load_constant(&mut self, val: i16) -> Result<u16, BackendError> {
+    /// Reserves the first free slot holding `size` bytes, unlike
+    /// `reserve_single` this fails rather than reusing a busy slot
+    fn reserve_sized(&mut self, size: u16) -> Result<Registers, BackendErrorKind> {
+        for (mask, reg, capacity) in SLOTS {
+            if self.ctx.used_regs & mask == 0 && size <= capacity {
+                self.ctx.used_regs |= mask;
+                return Ok(reg);
+            }
+        }
+        Err(BackendErrorKind::RanOutOfRegisters)
+    }
+
+    /// Literals are `int` unless they only fit in a `long`
+    fn load_constant(&mut self, val: i64) -> Result<Type, BackendError> {
+        let dest = if self.ctx.target_register == Registers::R0 {
+            self.reserve_single()
+        } else {
+            self.ctx.target_register
+        };
+        self.ctx.result = dest;
+
+        let ty = if i16::try_from(val).is_ok() { Type::I16 } else { Type::I32 };
+        for i in 0..ty.width().unwrap_or_default() {
+            self.assm.ldi(dest.add(i), ((val >> (8 * i)) & 0xff) as i16);
+        }
+        Ok(ty)
+    }
+
+    fn load_byte(&mut self, val: u8) -> Result<Type, BackendError> {
         let dest = if self.ctx.target_register == Registers::R0 {
             self.reserve_single()
         } else {
             self.ctx.target_register
         };
+        self.ctx.result = dest;
 
-        self.assm.ldi(dest, val & 0xff);
-        self.assm.ldi(dest.add(1), (val >> 8) & 0xff);
-        Ok(2)
+        self.assm.ldi(dest, val.into());
+        Ok(Type::U8)
+    }
+
+    /// Places the NUL terminated string in `.data` and loads its address
+    fn load_string(&mut self, value: &[u8]) -> Result<Type, BackendError> {
+        let label = format!(".Lstr{}", self.ctx.strings);
+        self.ctx.strings += 1;
+
+        let (section, current) = self.assm.position();
+        self.assm.select_section(self.ctx.data);
+        let addr = self.assm.create_label(&label);
+        self.assm.select_label(addr);
+        self.assm
+            .append_instruction(format!(".asciz \"{}\"", escape_string(value)));
+        self.assm.select_section(section);
+        self.assm.select_label(current);
+
+        let dest = if self.ctx.target_register == Registers::R0 {
+            self.reserve_single()
+        } else {
+            self.ctx.target_register
+        };
+        self.ctx.result = dest;
+        self.assm.ldi_label(dest, &label, false);
+        self.assm.ldi_label(dest.add(1), &label, true);
+        Ok(Type::Pointer(Box::new(Type::U8)))
     }
 
     fn emit_moffset(&mut self, offset: u16, size: u16) -> Result<(), BackendError> {
@@ -139,6 +323,7 @@ impl<'a> AVRBackend<'a> {
         } else {
             self.ctx.target_register
         };
+        self.ctx.result = dest;
 
         for i in offset + 1..offset + size + 1 {
             self.assm
@@ -148,21 +333,55 @@ impl<'a> AVRBackend<'a> {
         Ok(())
     }
 
-    fn load_variable(&mut self, name: String) -> Result<u16, BackendError> {
-        for var in self.ctx.locals.clone().iter() {
-            if var.name == name {
-                self.emit_moffset(var.stack_offset, var.size)?;
-                return Ok(var.size);
-            }
+    fn load_variable(&mut self, id: ExprId) -> Result<Type, BackendError> {
+        let var = self
+            .local(self.resolution.uses.get(id))
+            .map_err(|kind| self.error(kind, self.arena.span(id)))?;
+        // Arrays live in memory only, they are wider than any value register
+        if var.size > slot_capacity(Registers::R24) {
+            return Err(self.error(BackendErrorKind::RanOutOfRegisters, self.arena.span(id)));
+        }
+        self.emit_moffset(var.stack_offset, var.size)?;
+        Ok(var.ty)
+    }
+
+    /// Widens the value of type `from` held from `reg` on to `to`, sign
+    /// extending signed values. Narrowing keeps the low bytes where they
+    /// are, so it emits nothing
+    fn convert(&mut self, reg: Registers, from: &Type, to: &Type) {
+        let (Some(from_size), Some(to_size)) = (type_size(from), type_size(to)) else {
+            return;
+        };
+        if from_size == 0 || to_size <= from_size {
+            return;
+        }
+        let fill = reg.add(from_size as u8);
+        if from.is_signed() {
+            // Shifting the sign bit into carry, `sbc` then yields 0 or 0xff
+            self.assm.mov(fill, reg.add(from_size as u8 - 1));
+            self.assm.lsl(fill);
+            self.assm.sbc(fill, fill);
+        } else {
+            self.assm.clr(fill);
         }
-        Err(BackendError::AssemblerError)
+        for i in from_size + 1..to_size {
+            self.assm.mov(reg.add(i as u8), fill);
+        }
+    }
+
+    fn emit_cast(&mut self, value: ExprId, ty: &Spanned<Type>) -> Result<Type, BackendError> {
+        self.resolve_size(ty)?;
+        let from = self.emit_expression(value, false, self.ctx.target_register)?;
+        self.convert(self.ctx.result, &from, &ty.1);
+        Ok(ty.1.clone())
     }
 
-    fn emit_binop(&mut self, expr: &Expr, lhs: &Expr, rhs: &Expr) -> Result<u16, BackendError> {
+    fn emit_binop(&mut self, id: ExprId, op: BinaryOp, lhs: ExprId, rhs: ExprId) -> Result<Type, BackendError> {
+        let span = self.arena.span(id);
         let rcouple: (Registers, Registers);
 
-        let lhs_size = self.emit_expression(lhs, false, Registers::R0)?;
-        let rhs_size = self.emit_expression(rhs, false, Registers::R0)?;
+        let lhs_ty = self.emit_expression(lhs, false, Registers::R0)?;
+        let rhs_ty = self.emit_expression(rhs, false, Registers::R0)?;
 
         if self.ctx.used_regs & R24 != 0
             && self.ctx.used_regs & R18 != 0
@@ -177,107 +396,131 @@ impl<'a> AVRBackend<'a> {
             self.ctx.used_regs &= !R16; // Set R16 as free bc result is stored in R18
             rcouple = (Registers::R18, Registers::R16);
         } else {
-            return Err(BackendError::RanOutOfRegisters);
+            return Err(self.error(BackendErrorKind::RanOutOfRegisters, span));
         }
 
-        match expr {
-            Expr::Add(_, _) => {
+        // Both operands are widened to the promoted type, pointers keep theirs
+        let ty = lhs_ty.promote(&rhs_ty).unwrap_or(lhs_ty.clone());
+        let size = type_size(&ty).unwrap_or_default();
+        if size > slot_capacity(rcouple.0) || size > slot_capacity(rcouple.1) {
+            return Err(self.error(BackendErrorKind::RanOutOfRegisters, span));
+        }
+        self.convert(rcouple.0, &lhs_ty, &ty);
+        self.convert(rcouple.1, &rhs_ty, &ty);
+        self.ctx.result = rcouple.0;
+
+        match op {
+            BinaryOp::Add => {
                 self.assm.add(rcouple.0, rcouple.1);
-                for i in 1..lhs_size {
+                for i in 1..size {
                     self.assm
                         .adc(rcouple.0.add(i as u8), rcouple.1.add(i as u8));
                 }
             }
-            Expr::Sub(_, _) => {}
-            _ => return Err(BackendError::UnsupportedBinaryOperation),
+            BinaryOp::Sub => {
+                self.assm.sub(rcouple.0, rcouple.1);
+                for i in 1..size {
+                    self.assm
+                        .sbc(rcouple.0.add(i as u8), rcouple.1.add(i as u8));
+                }
+            }
+            _ => return Err(self.error(BackendErrorKind::UnsupportedBinaryOperation, span)),
         }
 
-        Ok(lhs_size.max(rhs_size))
+        Ok(ty)
     }
 
-    fn resolve_function(&self, name: &str) -> Result<&Function, BackendError> {
-        for func in self.ctx.functions.iter() {
-            if func.name == name {
-                return Ok(func);
-            }
+    /// Arguments are widened to the parameter types and passed in
+    /// consecutive registers from R16 on
+    fn emit_call(&mut self, id: ExprId, args: &[ExprId]) -> Result<Type, BackendError> {
+        let func = self
+            .resolution
+            .uses
+            .get(id)
+            .and_then(|symbol| self.ctx.functions.get(*symbol))
+            .ok_or_else(|| self.error(BackendErrorKind::CannotResolveFunction, self.arena.span(id)))?;
+        let label = func.name.clone();
+        let ret = func.ret.clone();
+        let params = func.args.clone();
+        self.resolve_size(&ret)?;
+        if args.len() != params.len() {
+            return Err(self.error(BackendErrorKind::ArgumentCountMismatch, self.arena.span(id)));
         }
-        Err(BackendError::CannotResolveFunction)
-    }
-
-    fn emit_call(&mut self, name: &str, args: &Vec<Expr>) -> Result<u16, BackendError> {
-        let func = self.resolve_function(name)?;
-        let ret_size = self.resolve_size(&func.ret)?;
         let mut current_reg = Registers::R16;
-        let mut arg_size;
 
-        for arg in args {
-            arg_size = self.emit_expression(arg, true, current_reg)?;
+        for (arg, param) in args.iter().zip(&params) {
+            let ty = self.emit_expression(*arg, true, current_reg)?;
+            let arg_size = type_size(&ty).unwrap_or_default();
             for o in 0..arg_size {
                 if current_reg != Registers::R24 && self.ctx.target_register == Registers::R0 {
                     self.assm
                         .mov(current_reg.add(o as u8), Registers::R24.add((o) as u8));
                 }
             }
-            current_reg = current_reg.add(arg_size as u8);
+            self.convert(current_reg, &ty, param);
+            current_reg = current_reg.add(type_size(param).unwrap_or(arg_size) as u8);
         }
-        Ok(ret_size)
+        self.assm.rcall(&label);
+        self.ctx.result = Registers::R24;
+        Ok(ret.1)
     }
 
-    fn emit_expression(
-        &mut self,
-        expr: &Expr,
-        root: bool,
-        target_register: Registers,
-    ) -> Result<u16, BackendError> {
+    fn emit_expression(&mut self, id: ExprId, root: bool, target_register: Registers) -> Result<Type, BackendError> {
         // Root is to identify if the expression is the root of the operation tree
         self.ctx.target_register = target_register;
         if root {
             self.ctx.used_regs = EMPTY;
         }
-        match expr {
-            Expr::Number(Spanned(_, value)) => self.load_constant(*value as i16),
-            Expr::Ident(Spanned(_, name)) => self.load_variable(name.to_string()),
-            Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) => self.emit_binop(expr, lhs, rhs),
-            Expr::Call(name, args) => self.emit_call(name, args),
-            _ => Err(BackendError::UnsupportedValue),
+        let arena = self.arena;
+        match &arena[id] {
+            Node::Number(literal) => self.load_constant(literal.value),
+            Node::Char(value) => self.load_byte(*value),
+            Node::Str(value) => self.load_string(value),
+            Node::Ident(_) => self.load_variable(id),
+            Node::Binary(op @ (BinaryOp::Add | BinaryOp::Sub), lhs, rhs) => self.emit_binop(id, *op, *lhs, *rhs),
+            Node::Call(_, args) => self.emit_call(id, args),
+            Node::Cast(value, ty) => self.emit_cast(*value, ty),
+            _ => Err(self.error(BackendErrorKind::UnsupportedValue, arena.span(id))),
         }
     }
 
-    fn resolve_size(&self, ty: &Type) -> Result<u16, BackendError> {
-        match ty {
-            Type::Int => Ok(2),
-            Type::Other(other) => match other.as_str() {
-                "char" => Ok(1),
-                "long" => Ok(4),
-                _ => Err(BackendError::AssemblerError),
-            },
-            _ => Err(BackendError::AssemblerError),
-        }
+    fn resolve_size(&self, ty: &Spanned<Type>) -> Result<u16, BackendError> {
+        type_size(&ty.1).ok_or_else(|| self.error(BackendErrorKind::UnsupportedType, &ty.0))
     }
 
-    fn emit_declaration(
-        &mut self,
-        name: &str,
-        ty: &Type,
-        value: &Expr,
-    ) -> Result<(), BackendError> {
+    /// Every element of an array starts as `value`, the element goes through
+    /// R24 once and is then stored into each element of the stack slot
+    fn emit_declaration(&mut self, id: ExprId, ty: &Spanned<Type>, value: ExprId) -> Result<(), BackendError> {
         let size = self.resolve_size(ty)?;
+        let (element, count) = array_element(&ty.1);
+        let width = type_size(element).unwrap_or_default();
+        if size == 0 || width == 0 {
+            return Err(self.error(BackendErrorKind::UnsupportedType, &ty.0));
+        }
+        if width > slot_capacity(Registers::R24) {
+            return Err(self.error(BackendErrorKind::RanOutOfRegisters, &ty.0));
+        }
 
-        self.ctx.locals.push(Variable {
-            name: name.into(),
-            size,
-            stack_offset: self.ctx.stack_offset,
-        });
-
-        self.emit_expression(value, true, Registers::R24)?;
+        let value = self.emit_expression(value, true, Registers::R24)?;
+        self.convert(Registers::R24, &value, element);
 
-        let new_offset = self.ctx.stack_offset + 1;
+        let Some(&symbol) = self.resolution.decls.get(id) else {
+            return Err(self.error(BackendErrorKind::AssemblerError, self.arena.span(id)));
+        };
+        self.ctx.locals.insert(
+            symbol,
+            Variable {
+                ty: ty.1.clone(),
+                size,
+                stack_offset: self.ctx.stack_offset,
+            },
+        );
 
-        for i in new_offset..new_offset + size {
+        for i in 0..count * width {
             self.assm.std(
                 Registers::Y,
-                i,
-                Registers::R24.add((i - self.ctx.stack_offset - 1) as u8),
+                self.ctx.stack_offset + i + 1,
+                Registers::R24.add((i % width) as u8),
             )
         }
 
@@ -286,16 +529,130 @@ impl<'a> AVRBackend<'a> {
         Ok(())
     }
 
-    fn emit_return(&mut self, expr: &Expr) -> Result<(), BackendError> {
-        self.emit_expression(expr, true, Registers::R24)?;
+    fn emit_return(&mut self, expr: ExprId) -> Result<(), BackendError> {
+        if let Node::Empty = self.arena[expr] {
+            return Ok(());
+        }
+        let value = self.emit_expression(expr, true, Registers::R24)?;
+        let ret = self.ctx.ret.clone();
+        self.convert(Registers::R24, &value, &ret);
         Ok(())
     }
 
-    fn emit_statement(&mut self, stat: &Expr) -> Result<(), BackendError> {
+    /// Local declared as `symbol`, parameters have no stack slot yet
+    fn local(&self, symbol: Option<&SymbolId>) -> Result<Variable, BackendErrorKind> {
+        symbol
+            .and_then(|symbol| self.ctx.locals.get(*symbol))
+            .cloned()
+            .ok_or(BackendErrorKind::AssemblerError)
+    }
+
+    fn emit_asm_text(&mut self, text: &str) {
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            self.assm.append_instruction(line.to_string());
+        }
+    }
+
+    /// Operands get registers outside the clobbered slots, inputs and `+`
+    /// outputs are loaded from their stack slot before the text and outputs
+    /// are stored back after it. Clobbered callee-saved registers are
+    /// preserved around the text
+    fn emit_inline_asm(&mut self, id: ExprId, asm: &InlineAsm) -> Result<(), BackendError> {
+        let span = self.arena.span(id);
+        if !asm.has_operands() {
+            self.emit_asm_text(&asm.text);
+            return Ok(());
+        }
+        let source_key = self.source_key;
+        let error = |kind| BackendError {
+            kind,
+            location: Location::new(span.clone(), source_key),
+        };
+        let clobbers = asm
+            .clobbers
+            .iter()
+            .filter(|clobber| clobber.as_str() != "memory")
+            .map(|clobber| parse_register(clobber))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error)?;
+
+        self.ctx.used_regs = EMPTY;
+        for clobber in &clobbers {
+            for (mask, reg, capacity) in SLOTS {
+                let first = reg as u8;
+                if (first..first + capacity as u8).contains(&(*clobber as u8)) {
+                    self.ctx.used_regs |= mask;
+                }
+            }
+        }
+
+        let operands = asm
+            .outputs
+            .iter()
+            .map(|operand| (operand, true))
+            .chain(asm.inputs.iter().map(|operand| (operand, false)));
+        let symbols = self.resolution.operands.get(id);
+        let mut bound = Vec::new();
+        for (i, (operand, output)) in operands.enumerate() {
+            let constraint = operand.constraint.as_str();
+            let (read, class) = match constraint.chars().next() {
+                Some('=') if output => (false, &constraint[1..]),
+                Some('+') if output => (true, &constraint[1..]),
+                _ if output => return Err(error(BackendErrorKind::UnsupportedConstraint)),
+                _ => (true, constraint),
+            };
+            // Every allocator slot lies in the upper registers
+            if !matches!(class, "r" | "d") {
+                return Err(error(BackendErrorKind::UnsupportedConstraint));
+            }
+
+            let var = self.local(symbols.and_then(|symbols| symbols.get(i))).map_err(error)?;
+            let reg = self.reserve_sized(var.size).map_err(error)?;
+            if read {
+                self.ctx.target_register = reg;
+                self.emit_moffset(var.stack_offset, var.size)?;
+            }
+            bound.push((reg, var));
+        }
+
+        let registers: Vec<_> = bound.iter().map(|(reg, var)| (*reg, var.size)).collect();
+        let text = substitute_operands(&asm.text, &registers).map_err(error)?;
+        let saved: Vec<_> = clobbers.iter().copied().filter(is_callee_saved).collect();
+
+        for reg in &saved {
+            self.assm.push(*reg);
+        }
+        self.emit_asm_text(&text);
+        for reg in saved.iter().rev() {
+            self.assm.pop(*reg);
+        }
+        if clobbers.contains(&Registers::R1) {
+            // R1 is expected to always hold zero
+            self.assm.clr(Registers::R1);
+        }
+
+        for (reg, var) in bound.iter().take(asm.outputs.len()) {
+            for i in 0..var.size {
+                self.assm
+                    .std(Registers::Y, var.stack_offset + i + 1, reg.add(i as u8));
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_statement(&mut self, stat: ExprId) -> Result<(), BackendError> {
         //println!("{:?}", stat);
-        match stat {
-            Expr::Decl(name, ty, value) => self.emit_declaration(name, ty, value),
-            Expr::Return(expr) => self.emit_return(expr),
+        let arena = self.arena;
+        match &arena[stat] {
+            Node::Block(stats) => {
+                for stat in stats {
+                    self.emit_statement(*stat)?;
+                }
+                Ok(())
+            }
+            Node::Decl(_, ty, value) => self.emit_declaration(stat, ty, *value),
+            Node::Return(expr) => self.emit_return(*expr),
+            Node::InlineAsm(asm) => self.emit_inline_asm(stat, asm),
             _ => {
                 self.emit_expression(stat, true, Registers::R0)?;
                 Ok(())
@@ -303,6 +660,21 @@ impl<'a> AVRBackend<'a> {
         }
     }
 
+    fn emit_items(&mut self, items: &[ItemId]) -> Result<(), BackendError> {
+        let arena = self.arena;
+        for &item in items {
+            match &arena[item] {
+                Item::Function { ret, body, .. } => {
+                    if let Some(body) = body {
+                        self.emit_function(item, ret, *body)?;
+                    }
+                }
+                Item::Namespace { items, .. } => self.emit_items(items)?,
+            }
+        }
+        Ok(())
+    }
+
     pub fn process(&mut self) -> Result<(), BackendError> {
         self.assm.new_global("main");
 
@@ -311,30 +683,171 @@ impl<'a> AVRBackend<'a> {
 
         self.assm.select_section(self.ctx.text);
 
-        for node in self.nodes {
-            match node {
-                Expr::Function(name, ret, args, body) => {
-                    self.emit_function(name, ret, args, body)?;
-                }
-                _ => return Err(BackendError::UnsupportedValue),
-            }
-        }
+        self.declare_functions(&self.arena.root)?;
+        self.emit_items(&self.arena.root)?;
         println!("{}", self.assm.repr());
         Ok(())
     }
 }
 
-pub fn compile(source: Ast) {
-    let mut seb = AVRBackend::new(&source.root);
-    let _ = seb.process();
+/// Bytes available from `reg` on if it starts an allocator slot
+fn slot_capacity(reg: Registers) -> u16 {
+    SLOTS
+        .iter()
+        .find(|(_, first, _)| *first == reg)
+        .map_or(0, |(_, _, capacity)| *capacity)
+}
+
+/// Size in bytes of a value of type `ty`, pointers are 16 bit data
+/// addresses. Floats and structs are not supported yet
+fn type_size(ty: &Type) -> Option<u16> {
+    match ty {
+        Type::Void => Some(0),
+        Type::U8 | Type::I8 | Type::Bool => Some(1),
+        Type::U16 | Type::I16 | Type::Pointer(_) => Some(2),
+        Type::U32 | Type::I32 => Some(4),
+        Type::Array(element, len) => type_size(element)?.checked_mul(u16::try_from(*len).ok()?),
+        Type::Float | Type::Named(_) => None,
+    }
+}
+
+/// Innermost element type of `ty` and how many of them it holds, scalars
+/// are a single element
+fn array_element(ty: &Type) -> (&Type, u16) {
+    match ty {
+        Type::Array(element, len) => {
+            let (inner, count) = array_element(element);
+            (inner, count.saturating_mul(u16::try_from(*len).unwrap_or(u16::MAX)))
+        }
+        _ => (ty, 1),
+    }
+}
+
+/// Escapes `value` for a GNU as string directive
+fn escape_string(value: &[u8]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for &byte in value {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
+/// `r24` or `R24`
+fn parse_register(name: &str) -> Result<Registers, BackendErrorKind> {
+    name.strip_prefix(['r', 'R'])
+        .and_then(|index| index.parse::<u8>().ok())
+        .filter(|index| *index < 32)
+        .map(Registers::index)
+        .ok_or(BackendErrorKind::UnsupportedConstraint)
+}
+
+/// Registers a function must preserve under the avr-gcc calling convention
+fn is_callee_saved(reg: &Registers) -> bool {
+    matches!(*reg as u8, 2..=17 | 28 | 29)
+}
+
+/// Replaces `%N` with the register holding operand `N`, `%AN` to `%DN`
+/// select its bytes from the lowest one up and `%%` is a literal `%`.
+/// Operands are given as their first register and size in bytes
+fn substitute_operands(text: &str, operands: &[(Registers, u16)]) -> Result<String, BackendErrorKind> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            out.push('%');
+            continue;
+        }
+        let byte = match chars.next_if(|b| ('A'..='D').contains(b)) {
+            Some(b) => b as u8 - b'A',
+            None => 0,
+        };
+        let mut index = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            index.push(digit);
+        }
+        let (reg, size) = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| operands.get(index))
+            .ok_or(BackendErrorKind::AssemblerError)?;
+        if byte as u16 >= *size {
+            return Err(BackendErrorKind::UnsupportedConstraint);
+        }
+        out.push_str(&format!("{:?}", reg.add(byte)));
+    }
+    Ok(out)
+}
+
+/// Resolves names in `source`, checks its types and generates code for it,
+/// every error is added to `reports`. Nothing is generated for a program
+/// that does not resolve or type check
+pub fn compile(source: &Ast, source_key: SourceKey, reports: &mut ReportContext) -> Option<String> {
+    let arena = Arena::lower(source);
+    let resolution = Resolver::new(&arena, source_key).process(reports);
+    if reports.has_reports() {
+        return None;
+    }
+    TypeChecker::new(&arena, &resolution, source_key).process(reports);
+    if reports.has_reports() {
+        return None;
+    }
+    let mut seb = AVRBackend::new(&arena, &resolution, source_key);
+    match seb.process() {
+        Ok(()) => Some(seb.assm.repr()),
+        Err(err) => {
+            reports.extend([err.into_report()]);
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use ast::{Expr, Path};
+
     use super::*;
 
+    fn node(expr: Expr) -> Spanned<Expr> {
+        Spanned(0..0, expr)
+    }
+
+    fn function(name: &str, body: Vec<Expr>) -> Spanned<Expr> {
+        node(Expr::Function(ast::Function {
+            name: name.into(),
+            ret: Spanned(0..0, Type::I16),
+            params: vec![],
+            body: Some(Box::new(node(Expr::Block(body.into_iter().map(node).collect())))),
+            doc: None,
+        }))
+    }
+
+    /// Code for `nodes` after lowering and resolving them. Names which do
+    /// not resolve are left for the backend to trip over
+    fn generate(nodes: Vec<Spanned<Expr>>) -> (Result<(), BackendError>, String) {
+        let arena = Arena::lower(&Ast { root: nodes });
+        let resolution = Resolver::new(&arena, SourceKey::default()).process(&mut ReportContext::default());
+        let mut seb = AVRBackend::new(&arena, &resolution, SourceKey::default());
+        let result = seb.process();
+        (result, seb.assm.repr())
+    }
+
     #[test]
     fn backend_test0() {
+        let decl = |name: &str, value: i64| {
+            Expr::Decl(name.into(), Spanned(0..0, Type::I16), Box::new(node(Expr::Number(value.into()))))
+        };
+        let ident = |name: &str| Box::new(node(Expr::Ident(name.into())));
         let ast = Ast {
             /*
             int main() {
@@ -343,36 +856,399 @@ mod tests {
                 return x + y;
             }
             */
-            root: vec![Expr::Function(
-                "main".to_string(),
-                Type::Int,
-                vec![],
-                Box::new(Expr::Block(vec![
-                    Expr::Call("main".to_string(), vec![Expr::Number(Spanned(0..0, 10))]),
-                    /*Expr::Decl(
-                        "x".to_string(),
-                        "int".to_string(),
-                        Box::new(Expr::Number(3)),
-                    ),
-                    Expr::Decl(
-                        "y".to_string(),
-                        "int".to_string(),
-                        Box::new(Expr::Number(4)),
-                    ),
-                    Expr::Return(Box::new(Expr::Add(
-                        Box::new(Expr::Add(
-                            Box::new(Expr::Number(3)),
-                            Box::new(Expr::Ident("y".to_string())),
-                        )),
-                        Box::new(Expr::Add(
-                            Box::new(Expr::Number(2)),
-                            Box::new(Expr::Ident("x".to_string())),
-                        )),
-                    ))),*/
-                ])),
+            root: vec![function(
+                "main",
+                vec![
+                    decl("x", 10),
+                    decl("y", 20),
+                    Expr::Return(Box::new(node(Expr::Add(ident("x"), ident("y"))))),
+                ],
             )],
         };
 
-        compile(ast);
+        let mut reports = ReportContext::default();
+        let asm = compile(&ast, SourceKey::default(), &mut reports);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+        assert!(asm.is_some_and(|asm| asm.contains("main:")));
+    }
+
+    #[test]
+    fn calls_take_every_argument() {
+        let call = Spanned(20..28, Expr::Call("main".into(), vec![node(Expr::Number(10.into()))]));
+        let nodes = vec![node(Expr::Function(ast::Function {
+            name: "main".into(),
+            ret: Spanned(11..14, Type::I16),
+            params: vec![],
+            body: Some(Box::new(node(Expr::Block(vec![call])))),
+            doc: None,
+        }))];
+        let err = generate(nodes.clone()).0.err().unwrap();
+        assert_eq!(err.kind, BackendErrorKind::ArgumentCountMismatch);
+        assert_eq!(err.location.span(), &(20..28));
+
+        // Through `compile` the type checker reports it first
+        let mut reports = ReportContext::default();
+        assert_eq!(compile(&Ast { root: nodes }, SourceKey::default(), &mut reports), None);
+        let titles: Vec<_> = reports.iter().map(Report::title).collect();
+        assert_eq!(titles, ["this function takes 0 arguments but 1 argument was supplied"]);
+    }
+
+    #[test]
+    fn type_errors_stop_codegen() {
+        // `var s:u8 = "hello";`
+        let value = Box::new(Spanned(11..18, Expr::Str(b"hello".to_vec())));
+        let ast = Ast {
+            root: vec![function("main", vec![Expr::Decl("s".into(), Spanned(6..8, Type::U8), value)])],
+        };
+        let mut reports = ReportContext::default();
+        assert_eq!(compile(&ast, SourceKey::default(), &mut reports), None);
+        assert_eq!(reports.len(), 1, "{:?}", &reports[..]);
+        assert_eq!(reports[0].span(), &(11..18));
+    }
+
+    #[test]
+    fn namespaced_labels() {
+        let here = Path {
+            here: true,
+            segments: vec!["tst".into()],
+        };
+        let qualified = Path {
+            here: false,
+            segments: vec!["LSpace".into(), "tst".into()],
+        };
+        let nodes = vec![
+            function("tst", vec![]),
+            node(Expr::Namespace(ast::Namespace {
+                name: "LSpace".into(),
+                items: vec![
+                    function("tst", vec![]),
+                    function("halal", vec![Expr::Call(here, vec![]), Expr::Call("tst".into(), vec![])]),
+                ],
+                doc: None,
+            })),
+            function("main", vec![Expr::Call(qualified, vec![])]),
+        ];
+
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        let calls: Vec<_> = asm
+            .lines()
+            .filter(|line| line.contains("rcall") || line.ends_with(':'))
+            .map(str::trim)
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                "tst:",
+                "LSpace.tst:",
+                "LSpace.halal:",
+                "rcall LSpace.tst",
+                "rcall LSpace.tst",
+                "main:",
+                "rcall LSpace.tst",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_carry_locations() {
+        let call = Spanned(20..29, Expr::Call("missing".into(), vec![]));
+        let nodes = vec![node(Expr::Function(ast::Function {
+            name: "main".into(),
+            ret: Spanned(11..14, Type::I16),
+            params: vec![],
+            body: Some(Box::new(node(Expr::Block(vec![call])))),
+            doc: None,
+        }))];
+        let err = generate(nodes).0.err().unwrap();
+        assert_eq!(err.kind, BackendErrorKind::CannotResolveFunction);
+        assert_eq!(err.location.span(), &(20..29));
+        assert_eq!(err.into_report().title(), "cannot resolve function");
+    }
+
+    #[test]
+    fn reject_duplicate_definitions() {
+        let twice = |name: &str| {
+            let mut nodes = vec![function(name, vec![]), function(name, vec![])];
+            nodes[1].0 = 30..40;
+            nodes
+        };
+        let err = generate(twice("f")).0.err().unwrap();
+        assert_eq!(err.kind, BackendErrorKind::DuplicateFunction);
+        assert_eq!(err.location.span(), &(30..40));
+
+        // Through `compile` sema reports it and nothing is generated
+        let mut reports = ReportContext::default();
+        compile(&Ast { root: twice("f") }, SourceKey::default(), &mut reports);
+        let titles: Vec<_> = reports.iter().map(Report::title).collect();
+        assert_eq!(titles, ["`f` is defined multiple times"]);
+    }
+
+    #[test]
+    fn sized_types() {
+        let pointer = Type::Pointer(Box::new(Type::I32));
+        assert_eq!(type_size(&Type::Bool), Some(1));
+        assert_eq!(type_size(&pointer), Some(2));
+        assert_eq!(type_size(&Type::Array(Box::new(Type::U32), 3)), Some(12));
+        assert_eq!(type_size(&Type::Named("Point".into())), None);
+
+        let decl = |ty: Type| Expr::Decl("v".into(), Spanned(4..8, ty), Box::new(node(Expr::Number(1.into()))));
+        let nodes = vec![function("main", vec![decl(Type::I32)])];
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        assert!(asm.contains("    std Y+4, R27\n"));
+
+        let nodes = vec![function("main", vec![decl(Type::Void)])];
+        let err = generate(nodes).0.err().unwrap();
+        assert_eq!(err.kind, BackendErrorKind::UnsupportedType);
+        assert_eq!(err.location.span(), &(4..8));
+    }
+
+    #[test]
+    fn arrays_are_initialised_in_memory() {
+        let array = |element: Type, len: usize| Type::Array(Box::new(element), len);
+        let decl = |ty: Type| Expr::Decl("buf".into(), Spanned(4..8, ty), Box::new(node(Expr::Number(1.into()))));
+        let nodes = vec![function("main", vec![decl(array(Type::U8, 16))])];
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        assert_eq!(asm.matches("    std Y+").count(), 16, "{asm}");
+        assert!(asm.contains("    std Y+16, R24\n"), "{asm}");
+        assert!(!asm.contains(", R25\n") && !asm.contains(", R28\n"), "{asm}");
+        assert_eq!(asm.matches("rcall .+0").count(), 8);
+        assert_eq!(asm.matches("pop R0").count(), 16);
+
+        let nodes = vec![function("main", vec![decl(array(array(Type::I16, 2), 2))])];
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        assert!(asm.contains("    std Y+7, R24\n    std Y+8, R25\n"), "{asm}");
+
+        let read = Expr::Return(Box::new(Spanned(9..12, Expr::Ident("buf".into()))));
+        let nodes = vec![function("main", vec![decl(array(Type::U8, 16)), read])];
+        let err = generate(nodes).0.err().unwrap();
+        assert_eq!(err.kind, BackendErrorKind::RanOutOfRegisters);
+        assert_eq!(err.location.span(), &(9..12));
+    }
+
+    #[test]
+    fn locals_are_per_function() {
+        let decl = |name: &str| Expr::Decl(name.into(), Spanned(0..0, Type::I16), Box::new(node(Expr::Number(1.into()))));
+        let nodes = vec![
+            function("f", vec![decl("y"), decl("x")]),
+            function("g", vec![decl("x")]),
+        ];
+        let (result, g) = generate(nodes);
+        assert!(result.is_ok());
+        let g = &g[g.find("g:").unwrap()..];
+        assert!(g.contains("    std Y+1, R24\n"), "{g}");
+        assert!(!g.contains("Y+3"), "{g}");
+
+        let read = Expr::Return(Box::new(Spanned(7..8, Expr::Ident("y".into()))));
+        let nodes = vec![function("f", vec![decl("y")]), function("g", vec![read])];
+        let err = generate(nodes).0.err().unwrap();
+        assert_eq!(err.location.span(), &(7..8));
+    }
+
+    #[test]
+    fn shadowed_locals_end_with_their_block() {
+        let decl = |ty: Type, value: Expr| Expr::Decl("x".into(), Spanned(0..0, ty), Box::new(node(value)));
+        let inner = Expr::Block(vec![node(decl(Type::U8, Expr::Char(b'a')))]);
+        let read = Expr::Return(Box::new(node(Expr::Ident("x".into()))));
+        let nodes = vec![function("main", vec![decl(Type::I16, Expr::Number(1.into())), inner, read])];
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        // The outer `x` at Y+1 is read, not the `u8` at Y+3
+        assert!(asm.contains("    ldd R24, Y+1\n    ldd R25, Y+2\n"), "{asm}");
+        assert!(!asm.contains("ldd R24, Y+3"), "{asm}");
+    }
+
+    #[test]
+    fn widen_operands_and_values() {
+        let ident = |name: &str| Box::new(node(Expr::Ident(name.into())));
+        let decl = |name: &str, ty: Type, value: Expr| {
+            Expr::Decl(name.into(), Spanned(0..0, ty), Box::new(node(value)))
+        };
+        let cast = Expr::Cast(Box::new(node(Expr::Number(255.into()))), Spanned(0..0, Type::I8));
+        let nodes = vec![function(
+            "main",
+            vec![
+                decl("a", Type::U8, Expr::Char(b'x')),
+                decl("b", Type::I32, Expr::Number(70000.into())),
+                decl("c", Type::I8, cast),
+                decl("d", Type::I16, Expr::Ident("c".into())),
+                Expr::Return(Box::new(node(Expr::Add(ident("a"), ident("b"))))),
+            ],
+        )];
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+
+        // 70000 = 0x00011170
+        assert!(asm.contains("    ldi R24, 112\n    ldi R25, 17\n    ldi R26, 1\n    ldi R27, 0\n"), "{asm}");
+        // `c` is sign extended into `d`
+        assert!(asm.contains("    ldd R24, Y+6\n    mov R25, R24\n    lsl R25\n    sbc R25, R25\n    std Y+7, R24\n    std Y+8, R25\n"), "{asm}");
+        // `a` is zero extended to the width of `b` before adding
+        assert!(asm.contains(
+            "    ldd R24, Y+1\n    ldd R18, Y+2\n    ldd R19, Y+3\n    ldd R20, Y+4\n    ldd R21, Y+5\n    clr R25\n    mov R26, R25\n    mov R27, R25\n    add R24, R18\n    adc R25, R19\n    adc R26, R20\n    adc R27, R21\n"
+        ), "{asm}");
+    }
+
+    #[test]
+    fn subtract_over_every_byte() {
+        let ident = |name: &str| Box::new(node(Expr::Ident(name.into())));
+        let decl = |name: &str, ty: Type| Expr::Decl(name.into(), Spanned(0..0, ty), Box::new(node(Expr::Number(7.into()))));
+        let nodes = vec![function(
+            "main",
+            vec![
+                decl("a", Type::I32),
+                decl("b", Type::I16),
+                Expr::Return(Box::new(node(Expr::Sub(ident("a"), ident("b"))))),
+            ],
+        )];
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        // `b` is sign extended to four bytes first
+        assert!(asm.contains(
+            "    lsl R20
+    sbc R20, R20
+    mov R21, R20
+    sub R24, R18
+    sbc R25, R19
+    sbc R26, R20
+    sbc R27, R21
+"
+        ), "{asm}");
+    }
+
+    #[test]
+    fn inline_asm_lines() {
+        let nodes = vec![function(
+            "main",
+            vec![Expr::InlineAsm(InlineAsm {
+                text: "\n    sbi 0x04, 5\n\n    sbi 0x05, 5 ; led on\n".into(),
+                ..Default::default()
+            })],
+        )];
+
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        assert!(asm.contains("    sbi 0x04, 5\n    sbi 0x05, 5 ; led on\n"));
+    }
+
+    #[test]
+    fn inline_asm_operands() {
+        let operand = |constraint: &str, variable: &str| ast::AsmOperand {
+            constraint: constraint.into(),
+            variable: variable.into(),
+        };
+        let decl = |name: &str| {
+            Expr::Decl(name.into(), Spanned(0..0, Type::I16), Box::new(node(Expr::Number(1.into()))))
+        };
+        let main = |asm: InlineAsm| vec![function("main", vec![decl("x"), decl("y"), Expr::InlineAsm(asm)])];
+
+        let nodes = main(InlineAsm {
+            text: "mov %A0, %1\nmov %B0, %B1 ; 100%%".into(),
+            outputs: vec![operand("=r", "x")],
+            inputs: vec![operand("r", "y")],
+            clobbers: vec!["r2".into(), "r24".into()],
+        });
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        // R24 is clobbered so operands go to R18 and R16, y lives at Y+3
+        assert!(asm.contains(
+            "    ldd R16, Y+3\n\
+             \x20   ldd R17, Y+4\n\
+             \x20   push R2\n\
+             \x20   mov R18, R16\n\
+             \x20   mov R19, R17 ; 100%\n\
+             \x20   pop R2\n\
+             \x20   std Y+1, R18\n\
+             \x20   std Y+2, R19\n"
+        ));
+
+        let nodes = main(InlineAsm {
+            text: "nop".into(),
+            outputs: vec![operand("r", "x")],
+            ..Default::default()
+        });
+        assert!(matches!(generate(nodes).0, Err(e) if e.kind == BackendErrorKind::UnsupportedConstraint));
+
+        // x is two bytes wide, it has no third byte
+        let nodes = main(InlineAsm {
+            text: "clr %C0".into(),
+            outputs: vec![operand("=r", "x")],
+            ..Default::default()
+        });
+        assert!(matches!(generate(nodes).0, Err(e) if e.kind == BackendErrorKind::UnsupportedConstraint));
+
+        let nodes = main(InlineAsm {
+            text: "nop".into(),
+            clobbers: vec!["r32".into()],
+            ..Default::default()
+        });
+        assert!(matches!(generate(nodes).0, Err(e) if e.kind == BackendErrorKind::UnsupportedConstraint));
+    }
+
+    #[test]
+    fn string_and_char_constants() {
+        let nodes = vec![function(
+            "main",
+            vec![
+                Expr::Decl("c".into(), Spanned(0..0, Type::U8), Box::new(node(Expr::Char(b'a')))),
+                Expr::Return(Box::new(node(Expr::Str(b"say \"hi\"\r\n\xff".to_vec())))),
+            ],
+        )];
+
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        assert!(asm.starts_with(
+            ".global main\n.section .data\n.Lstr0:\n    .asciz \"say \\\"hi\\\"\\015\\012\\377\"\n"
+        ));
+        assert!(asm.contains("    ldi R24, 97\n    std Y+1, R24\n"));
+        assert!(asm.contains("    ldi R24, lo8(.Lstr0)\n    ldi R25, hi8(.Lstr0)\n"));
+    }
+
+    #[test]
+    fn calls_are_order_independent() {
+        let qualified = Path {
+            here: false,
+            segments: vec!["later".into(), "tst".into()],
+        };
+        let ext = node(Expr::Function(ast::Function {
+            name: "ext".into(),
+            ret: Spanned(0..0, Type::I16),
+            params: vec![],
+            body: None,
+            doc: None,
+        }));
+        let nodes = vec![
+            ext,
+            function(
+                "main",
+                vec![
+                    Expr::Call("rec".into(), vec![]),
+                    Expr::Call(qualified, vec![]),
+                    Expr::Call("ext".into(), vec![]),
+                ],
+            ),
+            function("rec", vec![Expr::Call("rec".into(), vec![])]),
+            node(Expr::Namespace(ast::Namespace {
+                name: "later".into(),
+                items: vec![function("tst", vec![])],
+                doc: None,
+            })),
+        ];
+
+        let (result, asm) = generate(nodes);
+        assert!(result.is_ok());
+        let calls: Vec<_> = asm
+            .lines()
+            .filter(|line| line.contains("rcall") || line.ends_with(':'))
+            .map(str::trim)
+            .map(String::from)
+            .collect();
+        assert_eq!(
+            calls,
+            ["main:", "rcall rec", "rcall later.tst", "rcall ext", "rec:", "rcall rec", "later.tst:"]
+        );
     }
 }
@@ -1,265 +1,704 @@
-use std::iter::Enumerate;
+//! Lexical grammar of soel
+//!
+//! ```text
+//! identifier = "r#"? [A-Za-z_] [A-Za-z0-9_]*
+//! keyword    = "func" | "return" | "var" | "asm" | "if" | "then" | "else"
+//!            | "namespace" | "here" | "as"
+//! number     = [0-9] [0-9_]* | "0x" [0-9A-Fa-f_]+ | "0b" [01_]+ | "0o" [0-7_]+
+//! string     = '"' (char | escape)* '"'
+//! character  = "'" (char | escape) "'"
+//! escape     = "\\" [ntr0\\"'] | "\\x" hex hex
+//! comment    = "//" line | "/*" (comment | any)* "*/"
+//! doc        = "///" line
+//! ```
+//!
+//! Keywords are never identifiers, `r#if` names an identifier `if`. Only
+//! ASCII is accepted outside of strings, comments and asm bodies.
 
-pub enum LexerError {
-    InvalidToken,
-    InvalidSymbol
+use logos::{Filter, Logos};
+use reports::{sourcemap::SourceKey, IntoReport, Level, Location, Report};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexerErrorKind {
+    InvalidToken(char),
+    UnterminatedAsmBlock,
+    MalformedNumber,
+    NumberOverflow,
+    UnterminatedString,
+    UnterminatedChar,
+    InvalidEscape,
+    InvalidCharLiteral,
+    UnterminatedComment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexerError {
+    pub kind: LexerErrorKind,
+    pub location: Location,
+}
+
+impl IntoReport for LexerError {
+    fn into_report(self) -> Report {
+        let (title, description) = match self.kind {
+            LexerErrorKind::InvalidToken(c) => ("invalid token", Some(format!("unexpected character `{c}`"))),
+            LexerErrorKind::UnterminatedAsmBlock => ("unterminated asm block", Some("missing closing `}`".into())),
+            LexerErrorKind::MalformedNumber => ("malformed number literal", None),
+            LexerErrorKind::NumberOverflow => (
+                "number literal out of range",
+                Some(format!("literals must not exceed {}", u32::MAX)),
+            ),
+            LexerErrorKind::UnterminatedString => ("unterminated string", Some("missing closing `\"`".into())),
+            LexerErrorKind::UnterminatedChar => ("unterminated character literal", Some("missing closing `'`".into())),
+            LexerErrorKind::InvalidEscape => (
+                "invalid escape sequence",
+                Some("expected one of `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"`, `\\'` or `\\xNN`".into()),
+            ),
+            LexerErrorKind::InvalidCharLiteral => (
+                "invalid character literal",
+                Some("a character literal holds exactly one ASCII character or escape".into()),
+            ),
+            LexerErrorKind::UnterminatedComment => ("unterminated block comment", Some("missing closing `*/`".into())),
+        };
+        Report::new(
+            Level::Error,
+            self.location.span().clone(),
+            self.location.source_key(),
+            title,
+            description,
+        )
+    }
 }
 
-pub type Span = std::ops::Range<usize>;
-#[derive(Debug, PartialEq)]
-#[allow(dead_code)]
-pub struct Spanned<T>(Span, pub T);
+/// Words lexed as keywords, never as identifiers. A name spelled like one
+/// has to be written as a raw `r#name`. Every entry needs a matching
+/// `#[token]` on `Token`
+pub const KEYWORDS: [(&str, TokenKind); 10] = [
+    ("func", TokenKind::Function),
+    ("return", TokenKind::Return),
+    ("var", TokenKind::Var),
+    ("asm", TokenKind::Asm),
+    ("if", TokenKind::If),
+    ("then", TokenKind::Then),
+    ("else", TokenKind::Else),
+    ("namespace", TokenKind::Namespace),
+    ("here", TokenKind::Here),
+    ("as", TokenKind::As),
+];
+
+/// Byte offsets into the lexed source, the same spans `reports` and the
+/// `SourceMap` work with
+pub use reports::Span;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T>(pub Span, pub T);
+
+/// Integer literal, the text is kept as written so that later passes can
+/// range check it against the type it is used as
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberLiteral {
+    pub value: u32,
+    pub text: String,
+}
+
+#[derive(Logos, Debug, Clone, PartialEq)]
+#[logos(extras = LexerExtras)]
 pub enum Token {
-    Number(Spanned<i32>),
+    #[regex("[0-9][0-9A-Za-z_]*", number)]
+    Number(Spanned<NumberLiteral>),
+    /// `[A-Za-z_][A-Za-z0-9_]*`, or a raw `r#name` which may spell a
+    /// keyword and holds the name without the prefix
+    #[regex("[A-Za-z_][A-Za-z0-9_]*", |lex| Spanned(lex.span(), lex.slice().to_string()))]
+    #[regex("r#[A-Za-z_][A-Za-z0-9_]*", |lex| Spanned(lex.span(), lex.slice()[2..].to_string()))]
     Identifier(Spanned<String>),
-    String(Spanned<String>),
+    /// Bytes of a string literal, text as UTF-8 and escapes as written
+    #[token("\"", string)]
+    String(Spanned<Vec<u8>>),
+    /// 8-bit character literal, `'a'` or `'\n'`
+    #[token("'", char_literal)]
+    Char(Spanned<u8>),
+    /// Raw text between the braces of an `asm { ... }` block, produced by
+    /// `lex` from the `{` following an `asm` keyword
+    AsmBody(Spanned<String>),
 
+    #[token("return", span)]
     Return(Span),
+    #[token("func", span)]
     Function(Span),
+    #[token("var", span)]
     Var(Span),
+    #[token("asm", asm)]
     Asm(Span),
+    #[token("if", span)]
     If(Span),
+    #[token("then", span)]
     Then(Span),
+    #[token("else", span)]
     Else(Span),
+    #[token("namespace", span)]
     Namespace(Span),
+    #[token("here", span)]
     Here(Span),
+    #[token("as", span)]
+    As(Span),
 
+    #[token("+", span)]
     Plus(Span),
+    #[token("-", span)]
     Minus(Span),
+    #[token("*", span)]
     Mul(Span),
+    #[token("/", span)]
     Div(Span),
+    #[token("**", span)]
     Pow(Span),
+    #[token("++", span)]
     Increment(Span),
+    #[token("--", span)]
     Decrease(Span),
 
+    #[token(";", span)]
     Semicolon(Span),
+    #[token(":", span)]
     Colon(Span),
+    #[token("$", span)]
     Dollar(Span),
 
+    #[token("==", span)]
     Eqq(Span),
+    #[token("=", span)]
     Eq(Span),
+    #[token("!", span)]
     Not(Span),
+    #[token("!=", span)]
     NotEq(Span),
 
+    #[token("(", span)]
     LParen(Span),
+    #[token(")", span)]
     RParen(Span),
+    #[token("{", span)]
     LBrace(Span),
+    #[token("}", span)]
     RBrace(Span),
+    #[token("[", span)]
     LBracket(Span),
+    #[token("]", span)]
     RBracket(Span),
+    #[token(",", span)]
     Comma(Span),
+    #[token(".", span)]
     Dot(Span),
 
+    #[token(">", span)]
     Greater(Span),
+    #[token("<", span)]
     Less(Span),
 
+    /// `// ...` or a `/* ... */` block, which may nest. Trivia, only kept
+    /// by `lex_lossless`
+    #[regex("//[^\n]*", span)]
+    #[token("/*", block_comment)]
     Comment(Span),
+    /// `/// ...`, the text after the slashes without the line break
+    #[regex("///([^/\n][^\n]*)?", doc_comment, priority = 10)]
+    DocComment(Spanned<String>),
+    /// Trivia, only kept by `lex_lossless`
+    #[regex(r"[ \t\r\n]+", span)]
+    Whitespace(Span),
 }
 
-const KEYWRD_MAP: &[(&str, fn(Span) -> Token)] = &[
-    ("return", Token::Return),
-    ("function", Token::Function),
-    ("var", Token::Var),
-    ("asm", Token::Asm),
-    ("if", Token::If),
-    ("then", Token::Then),
-    ("else", Token::Else),
-    ("namespace", Token::Namespace),
-    ("here", Token::Here),
-];
+/// Span-insensitive discriminant of a `Token`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Number,
+    Identifier,
+    String,
+    Char,
+    AsmBody,
 
-const OPERATOR_MAP: &[(&str, fn(Span) -> Token)] = &[
-    ("+", Token::Plus),
-    ("-", Token::Minus),
-    ("*", Token::Mul),
-    ("/", Token::Div),
-    ("**", Token::Pow),
-    ("++", Token::Increment),
-    ("--", Token::Decrease),
-    (";", Token::Semicolon),
-    (":", Token::Colon),
-    ("$", Token::Dollar),
-    ("=", Token::Eq),
-    ("==", Token::Eqq),
-    ("!", Token::Not),
-    ("!=", Token::NotEq),
-    ("(", Token::LParen),
-    (")", Token::RParen),
-    ("{", Token::LBrace),
-    ("}", Token::RBrace),
-    ("[", Token::LBracket),
-    ("]", Token::RBracket),
-    (",", Token::Comma),
-    (".", Token::Dot),
-    (">", Token::Greater),
-    ("<", Token::Less),
-    ("//", Token::Comment),
-];
+    Return,
+    Function,
+    Var,
+    Asm,
+    If,
+    Then,
+    Else,
+    Namespace,
+    Here,
+    As,
+
+    Plus,
+    Minus,
+    Mul,
+    Div,
+    Pow,
+    Increment,
+    Decrease,
+
+    Semicolon,
+    Colon,
+    Dollar,
 
-struct Lexer<'a> {
-    input: Enumerate<std::str::Chars<'a>>,
-    tokens: Vec<Token>,
-    current: (usize, char),
+    Eqq,
+    Eq,
+    Not,
+    NotEq,
+
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    Dot,
+
+    Greater,
+    Less,
+
+    Comment,
+    DocComment,
+    Whitespace,
 }
 
-impl<'a> Lexer<'a> {
-    pub fn new(src: &'a str) -> Lexer<'a> {
-        Lexer {
-            input: src.chars().enumerate(),
-            tokens: Vec::new(),
-            current: (0, '\0'),
-        }
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TokenKind::Number => "number",
+            TokenKind::Identifier => "identifier",
+            TokenKind::String => "string",
+            TokenKind::Char => "character",
+            TokenKind::AsmBody => "asm block",
+            TokenKind::Return => "`return`",
+            TokenKind::Function => "`func`",
+            TokenKind::Var => "`var`",
+            TokenKind::Asm => "`asm`",
+            TokenKind::If => "`if`",
+            TokenKind::Then => "`then`",
+            TokenKind::Else => "`else`",
+            TokenKind::Namespace => "`namespace`",
+            TokenKind::Here => "`here`",
+            TokenKind::As => "`as`",
+            TokenKind::Plus => "`+`",
+            TokenKind::Minus => "`-`",
+            TokenKind::Mul => "`*`",
+            TokenKind::Div => "`/`",
+            TokenKind::Pow => "`**`",
+            TokenKind::Increment => "`++`",
+            TokenKind::Decrease => "`--`",
+            TokenKind::Semicolon => "`;`",
+            TokenKind::Colon => "`:`",
+            TokenKind::Dollar => "`$`",
+            TokenKind::Eqq => "`==`",
+            TokenKind::Eq => "`=`",
+            TokenKind::Not => "`!`",
+            TokenKind::NotEq => "`!=`",
+            TokenKind::LParen => "`(`",
+            TokenKind::RParen => "`)`",
+            TokenKind::LBrace => "`{`",
+            TokenKind::RBrace => "`}`",
+            TokenKind::LBracket => "`[`",
+            TokenKind::RBracket => "`]`",
+            TokenKind::Comma => "`,`",
+            TokenKind::Dot => "`.`",
+            TokenKind::Greater => "`>`",
+            TokenKind::Less => "`<`",
+            TokenKind::Comment => "comment",
+            TokenKind::DocComment => "doc comment",
+            TokenKind::Whitespace => "whitespace",
+        })
     }
+}
 
-    fn advance(&mut self) {
-        self.current = self.input.next().unwrap_or((0, '\0'));
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Number(_) => TokenKind::Number,
+            Token::Identifier(_) => TokenKind::Identifier,
+            Token::String(_) => TokenKind::String,
+            Token::Char(_) => TokenKind::Char,
+            Token::AsmBody(_) => TokenKind::AsmBody,
+            Token::Return(_) => TokenKind::Return,
+            Token::Function(_) => TokenKind::Function,
+            Token::Var(_) => TokenKind::Var,
+            Token::Asm(_) => TokenKind::Asm,
+            Token::If(_) => TokenKind::If,
+            Token::Then(_) => TokenKind::Then,
+            Token::Else(_) => TokenKind::Else,
+            Token::Namespace(_) => TokenKind::Namespace,
+            Token::Here(_) => TokenKind::Here,
+            Token::As(_) => TokenKind::As,
+            Token::Plus(_) => TokenKind::Plus,
+            Token::Minus(_) => TokenKind::Minus,
+            Token::Mul(_) => TokenKind::Mul,
+            Token::Div(_) => TokenKind::Div,
+            Token::Pow(_) => TokenKind::Pow,
+            Token::Increment(_) => TokenKind::Increment,
+            Token::Decrease(_) => TokenKind::Decrease,
+            Token::Semicolon(_) => TokenKind::Semicolon,
+            Token::Colon(_) => TokenKind::Colon,
+            Token::Dollar(_) => TokenKind::Dollar,
+            Token::Eqq(_) => TokenKind::Eqq,
+            Token::Eq(_) => TokenKind::Eq,
+            Token::Not(_) => TokenKind::Not,
+            Token::NotEq(_) => TokenKind::NotEq,
+            Token::LParen(_) => TokenKind::LParen,
+            Token::RParen(_) => TokenKind::RParen,
+            Token::LBrace(_) => TokenKind::LBrace,
+            Token::RBrace(_) => TokenKind::RBrace,
+            Token::LBracket(_) => TokenKind::LBracket,
+            Token::RBracket(_) => TokenKind::RBracket,
+            Token::Comma(_) => TokenKind::Comma,
+            Token::Dot(_) => TokenKind::Dot,
+            Token::Greater(_) => TokenKind::Greater,
+            Token::Less(_) => TokenKind::Less,
+            Token::Comment(_) => TokenKind::Comment,
+            Token::DocComment(_) => TokenKind::DocComment,
+            Token::Whitespace(_) => TokenKind::Whitespace,
+        }
     }
 
-    fn process_digit(&mut self) -> Result<(), LexerError> {
-        let mut span: (usize, usize) = (self.current.0, self.current.0);
-        let mut strep = String::new();
-        while self.current.1 == '0'
-            || self.current.1 == 'b'
-            || self.current.1 == 'x'
-            || self.current.1.is_digit(16)
-        {
-            strep.push(self.current.1);
-            span.1 += 1;
-            self.advance();
-        }
-        let res;
-        if strep.starts_with("0x") {
-            res = i32::from_str_radix(strep.trim_start_matches("0x"), 16).unwrap();
-        } else if strep.starts_with("0b") {
-            res = i32::from_str_radix(strep.trim_start_matches("0b"), 2).unwrap();
-        } else {
-            res = strep.parse().unwrap();
+    pub fn span(&self) -> Span {
+        match self {
+            Token::Number(Spanned(span, _))
+            | Token::Identifier(Spanned(span, _))
+            | Token::String(Spanned(span, _))
+            | Token::Char(Spanned(span, _))
+            | Token::AsmBody(Spanned(span, _))
+            | Token::DocComment(Spanned(span, _)) => span.clone(),
+            Token::Return(span) | Token::Function(span) | Token::Var(span)
+            | Token::Asm(span) | Token::If(span) | Token::Then(span) | Token::Else(span)
+            | Token::Namespace(span) | Token::Here(span) | Token::As(span) | Token::Plus(span)
+            | Token::Minus(span) | Token::Mul(span) | Token::Div(span) | Token::Pow(span)
+            | Token::Increment(span) | Token::Decrease(span) | Token::Semicolon(span)
+            | Token::Colon(span) | Token::Dollar(span) | Token::Eqq(span)
+            | Token::Eq(span) | Token::Not(span) | Token::NotEq(span)
+            | Token::LParen(span) | Token::RParen(span) | Token::LBrace(span)
+            | Token::RBrace(span) | Token::LBracket(span) | Token::RBracket(span)
+            | Token::Comma(span) | Token::Dot(span) | Token::Greater(span)
+            | Token::Less(span) | Token::Comment(span) | Token::Whitespace(span) => span.clone(),
         }
-        self.tokens
-            .push(Token::Number(Spanned(span.0..span.1, res)));
-        Ok(())
     }
 
-    fn process_identifier(&mut self) -> Result<(), LexerError> {
-        let mut span: (usize, usize) = (self.current.0, self.current.0);
-        let mut strep = String::new();
-        while self.current.1.is_alphanumeric() {
-            strep.push(self.current.1);
-            span.1 += 1;
-            self.advance();
+    /// Whitespace and plain comments, which carry no meaning for the parser
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Token::Comment(_) | Token::Whitespace(_))
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        let kind = self.kind();
+        KEYWORDS.iter().any(|(_, keyword)| *keyword == kind)
+    }
+}
+
+/// Where the lexer is within an `asm` statement, which decides whether a
+/// `{` opens a raw body
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AsmState {
+    /// Right after the `asm` keyword
+    Keyword,
+    /// Inside the operand list, nested this many parentheses deep
+    Operands(u32),
+    /// After the `)` closing the operand list
+    Closed,
+}
+
+impl AsmState {
+    /// State after the significant `token`, `None` once it can no longer be
+    /// followed by an asm body
+    fn advance(self, token: &Token) -> Option<AsmState> {
+        match (self, token) {
+            (AsmState::Keyword, Token::LParen(_)) => Some(AsmState::Operands(1)),
+            (AsmState::Operands(1), Token::RParen(_)) => Some(AsmState::Closed),
+            (AsmState::Operands(depth), Token::RParen(_)) => Some(AsmState::Operands(depth - 1)),
+            (AsmState::Operands(depth), Token::LParen(_)) => Some(AsmState::Operands(depth + 1)),
+            (AsmState::Operands(_), Token::Semicolon(_) | Token::LBrace(_) | Token::RBrace(_)) => None,
+            (AsmState::Operands(_), token) if token.is_keyword() => None,
+            (AsmState::Operands(_), _) => Some(self),
+            _ => None,
         }
+    }
+}
+
+/// State threaded through the logos lexer by its callbacks
+#[derive(Debug, Default)]
+pub struct LexerExtras {
+    source_key: SourceKey,
+    errors: Vec<LexerError>,
+    /// Set by `asm`, a `{` right after the keyword or its operand list
+    /// opens a raw body
+    asm_pending: Option<AsmState>,
+}
+
+impl LexerExtras {
+    fn error(&mut self, kind: LexerErrorKind, span: Span) {
+        self.errors.push(LexerError {
+            kind,
+            location: Location::new(span, self.source_key),
+        });
+    }
+}
+
+type Lexer<'a> = logos::Lexer<'a, Token>;
+
+fn span(lex: &mut Lexer) -> Span {
+    lex.span()
+}
 
-        for (kwrd, token) in KEYWRD_MAP.iter() {
-            if kwrd == &strep {
-                self.tokens.push(token(span.0..span.1));
-                return Ok(());
+fn asm(lex: &mut Lexer) -> Span {
+    lex.extras.asm_pending = Some(AsmState::Keyword);
+    lex.span()
+}
+
+/// Skips a `/* ... */` comment, nested blocks have to be closed as well
+fn block_comment(lex: &mut Lexer) -> Filter<Span> {
+    let start = lex.span();
+    let rest = lex.remainder();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < rest.len() {
+        if rest[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if rest[i..].starts_with("*/") {
+            i += 2;
+            if depth == 0 {
+                lex.bump(i);
+                return Filter::Emit(lex.span());
             }
+            depth -= 1;
+        } else {
+            i += rest[i..].chars().next().map_or(1, char::len_utf8);
         }
+    }
+    lex.bump(rest.len());
+    lex.extras.error(LexerErrorKind::UnterminatedComment, start);
+    Filter::Skip
+}
+
+fn doc_comment(lex: &mut Lexer) -> Spanned<String> {
+    let text = &lex.slice()[3..];
+    let text = text.strip_prefix(' ').unwrap_or(text).trim_end_matches('\r');
+    Spanned(lex.span(), text.to_string())
+}
 
-        self.tokens
-            .push(Token::Identifier(Spanned(span.0..span.1, strep)));
-        Ok(())
+/// `[0-9][0-9_]*`, or digits of the radix after a `0x`, `0b` or `0o`
+/// prefix. A malformed literal still yields a token so that parsing goes on
+fn number(lex: &mut Lexer) -> Spanned<NumberLiteral> {
+    // Letters glued to a literal are part of it, `12abc` is malformed
+    let text = lex.slice();
+    let (radix, digits) = match text.get(..2) {
+        Some("0x") => (16, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        _ => (10, text),
+    };
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    let value = if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        Err(LexerErrorKind::MalformedNumber)
+    } else {
+        // Every digit is valid, so this can only overflow
+        u32::from_str_radix(&digits, radix).map_err(|_| LexerErrorKind::NumberOverflow)
+    };
+    if let Err(kind) = value {
+        lex.extras.error(kind, lex.span());
     }
+    Spanned(
+        lex.span(),
+        NumberLiteral {
+            value: value.unwrap_or(0),
+            text: text.to_string(),
+        },
+    )
+}
+
+/// Decodes the escape sequence at the start of `rest`, which begins with
+/// `\`. Yields the byte and the length of the sequence, or the length of
+/// the offending text
+fn escape(rest: &str) -> Result<(u8, usize), usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let value = match chars.next() {
+        Some((_, 'n')) => b'\n',
+        Some((_, 't')) => b'\t',
+        Some((_, 'r')) => b'\r',
+        Some((_, '0')) => b'\0',
+        Some((_, '\\')) => b'\\',
+        Some((_, '"')) => b'"',
+        Some((_, '\'')) => b'\'',
+        Some((_, 'x')) => {
+            let mut value = 0;
+            for _ in 0..2 {
+                match chars.next() {
+                    Some((_, c)) if c.is_ascii_hexdigit() => value = value * 16 + c.to_digit(16).unwrap() as u8,
+                    // Leave the offending character to the caller
+                    Some((i, _)) => return Err(i),
+                    None => return Err(rest.len()),
+                }
+            }
+            return Ok((value, 4));
+        }
+        Some((i, c)) => return Err(i + c.len_utf8()),
+        None => return Err(1),
+    };
+    Ok((value, 2))
+}
 
-    fn process_string(&mut self) -> Result<(), LexerError> {
-        let mut span: (usize, usize) = (self.current.0, self.current.0);
-        let mut strep = String::new();
-        self.advance();
-        while self.current.1 != '"' {
-            strep.push(self.current.1);
-            span.1 += 1;
-            self.advance();
+fn string(lex: &mut Lexer) -> Filter<Spanned<Vec<u8>>> {
+    let start = lex.span().start;
+    let rest = lex.remainder();
+    let mut strep = Vec::new();
+    let mut i = 0;
+    loop {
+        let Some(c) = rest[i..].chars().next() else {
+            lex.bump(rest.len());
+            lex.extras.error(LexerErrorKind::UnterminatedString, start..start + 1);
+            return Filter::Skip;
+        };
+        match c {
+            '"' => break,
+            '\\' => match escape(&rest[i..]) {
+                Ok((byte, len)) => {
+                    strep.push(byte);
+                    i += len;
+                }
+                Err(len) => {
+                    let at = lex.span().end + i;
+                    lex.extras.error(LexerErrorKind::InvalidEscape, at..at + len);
+                    // A lone trailing `\` still has to move the scan along
+                    i += len;
+                }
+            },
+            c => {
+                strep.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                i += c.len_utf8();
+            }
         }
-        span.1 += 1;
-        self.advance();
-        self.tokens
-            .push(Token::String(Spanned(span.0..span.1, strep)));
-        Ok(())
     }
+    lex.bump(i + 1);
+    Filter::Emit(Spanned(lex.span(), strep))
+}
 
-    fn check_sym(&mut self, strep: String) -> Option<&fn(Span) -> Token> {
-        for (sym, token) in OPERATOR_MAP.iter() {
-            if sym == &strep {
-                return Some(token);
+fn char_literal(lex: &mut Lexer) -> Filter<Spanned<u8>> {
+    let start = lex.span().start;
+    let rest = lex.remainder();
+    let mut ascii = true;
+    let (value, mut i) = match rest.chars().next() {
+        Some('\\') => match escape(rest) {
+            Ok(escaped) => escaped,
+            Err(len) => {
+                lex.extras.error(LexerErrorKind::InvalidEscape, start + 1..start + 1 + len);
+                (0, len)
             }
+        },
+        Some('\'' | '\n') | None => (0, 0),
+        Some(c) => {
+            ascii = c.is_ascii();
+            (c as u8, c.len_utf8())
         }
-        return None;
+    };
+
+    if i > 0 && rest[i..].starts_with('\'') {
+        lex.bump(i + 1);
+        if !ascii {
+            // A placeholder keeps the parser from reporting the literal again
+            lex.extras.error(LexerErrorKind::InvalidCharLiteral, lex.span());
+            return Filter::Emit(Spanned(lex.span(), 0));
+        }
+        return Filter::Emit(Spanned(lex.span(), value));
     }
 
-    fn process_symbol(&mut self) -> Result<(), LexerError> {
-        let mut span: (usize, usize) = (self.current.0, self.current.0);
-        let mut strep = String::new();
-        while self
-            .check_sym(strep.clone() + &self.current.1.to_string())
-            .is_some()
-        {
-            strep.push(self.current.1);
-            span.1 += 1;
-            self.advance();
+    // Too many or no characters, skip to the closing quote on this line
+    i += rest[i..].find(['\'', '\n']).unwrap_or(rest.len() - i);
+    if rest[i..].starts_with('\'') {
+        lex.bump(i + 1);
+        lex.extras.error(LexerErrorKind::InvalidCharLiteral, lex.span());
+        return Filter::Emit(Spanned(lex.span(), 0));
+    }
+    lex.bump(i);
+    lex.extras.error(LexerErrorKind::UnterminatedChar, start..start + 1);
+    Filter::Skip
+}
+
+/// Raw text between the braces following `asm`, kept verbatim for the
+/// assembler including whitespace and newlines
+fn asm_body(lex: &mut Lexer, open: Span) -> Option<Token> {
+    lex.extras.asm_pending = None;
+    let rest = lex.remainder();
+    let mut depth = 0;
+    let close = rest.char_indices().find_map(|(i, c)| match c {
+        '{' => {
+            depth += 1;
+            None
         }
-        let _token = self
-            .check_sym(strep.clone());
-        match _token {
-            Some(token) => {
-                let tko = token(span.0..span.1);
-                match tko {
-                    Token::Comment(_) => {
-                        while self.current.1 != '\n' {
-                            self.advance();
-                        }
-                    }
-                    _ => {}
-                }
-                self.tokens.push(tko);
-            }
-            None => {
-                return Err(LexerError::InvalidSymbol);
-            }
+        '}' if depth == 0 => Some(i),
+        '}' => {
+            depth -= 1;
+            None
+        }
+        _ => None,
+    });
+    match close {
+        Some(i) => {
+            let body = rest[..i].to_string();
+            lex.bump(i + 1);
+            Some(Token::AsmBody(Spanned(open.start..lex.span().end, body)))
+        }
+        None => {
+            lex.bump(rest.len());
+            lex.extras.error(LexerErrorKind::UnterminatedAsmBlock, open);
+            None
         }
-        Ok(())
     }
+}
 
-    pub fn process(&mut self) -> Result<(), LexerError> {
-        self.advance();
-        while self.current.1 != '\0' {
-            match self.current.1 {
-                '0'..='9' => { self.process_digit()?; },
-                'a'..='z' | 'A'..='Z' => { self.process_identifier()?; },
-                '"' => { self.process_string()?; },
-                '+'..='/'
-                | '{'
-                | '}'
-                | '['
-                | ']'
-                | '('
-                | ')'
-                | '='
-                | ';'
-                | ':'
-                | '>'
-                | '<'
-                | '!'
-                | '$' => { self.process_symbol()?; } ,
-
-                ' ' | '\n' | '\t' => {
-                    self.advance();
+fn lex_tokens(src: &str, source_key: SourceKey, lossless: bool) -> (Vec<Token>, Vec<LexerError>) {
+    let mut lexer = Token::lexer_with_extras(
+        src,
+        LexerExtras {
+            source_key,
+            ..LexerExtras::default()
+        },
+    );
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next() {
+        match token {
+            Ok(Token::LBrace(open))
+                if matches!(lexer.extras.asm_pending, Some(AsmState::Keyword | AsmState::Closed)) =>
+            {
+                tokens.extend(asm_body(&mut lexer, open))
+            }
+            Ok(token) if token.is_trivia() => {
+                if lossless {
+                    tokens.push(token);
                 }
-                _ => {
-                    /* TODO: Err Handler */
-                    return Err(LexerError::InvalidToken);
+            }
+            Ok(token) => {
+                // `asm` itself has just set the state
+                if !matches!(token, Token::Asm(_)) {
+                    lexer.extras.asm_pending = lexer.extras.asm_pending.and_then(|state| state.advance(&token));
                 }
+                tokens.push(token);
+            }
+            // An invalid token is recorded and lexing goes on after it
+            Err(()) => {
+                let c = lexer.slice().chars().next().unwrap_or_default();
+                lexer.extras.error(LexerErrorKind::InvalidToken(c), lexer.span());
             }
         }
-        Ok(())
     }
+    (tokens, lexer.extras.errors)
 }
 
-pub fn lex(src: &str) -> Vec<Token> {
-    let mut l = Lexer::new(src);
-    let _ = l.process();
-    return l.tokens;
+/// Tokens of `src` along with every lexical error, located in `source_key`.
+/// Whitespace and comments are dropped, doc comments are kept for the parser
+pub fn lex(src: &str, source_key: SourceKey) -> (Vec<Token>, Vec<LexerError>) {
+    lex_tokens(src, source_key, false)
+}
+
+/// Like `lex` but keeps whitespace and comments as trivia tokens, so the
+/// spans of a valid source cover all of it
+pub fn lex_lossless(src: &str, source_key: SourceKey) -> (Vec<Token>, Vec<LexerError>) {
+    lex_tokens(src, source_key, true)
 }
 
 #[cfg(test)]
@@ -271,7 +710,8 @@ mod tests {
     #[test]
     fn lexer_test0() {
         let input = read_to_string("../syntax/syntax0.se").unwrap();
-        let result = lex(&input);
+        let (result, errors) = lex(&input, SourceKey::default());
+        assert!(errors.is_empty());
         result.into_iter().for_each(|token| println!("{token:?}"))
         /*let expected = vec![
             Token::Return,
@@ -283,4 +723,201 @@ mod tests {
         let result = lexer(input);
         assert_eq!(result, expected);*/
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn lex_asm_body_verbatim() {
+        let (result, _) = lex("asm {\n    ldi r24, 1\n    out 0x05, r24\n} x", SourceKey::default());
+        assert_eq!(
+            result,
+            vec![
+                Token::Asm(0..3),
+                Token::AsmBody(Spanned(4..40, "\n    ldi r24, 1\n    out 0x05, r24\n".into())),
+                Token::Identifier(Spanned(41..42, "x".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_asm_does_not_take_a_later_brace() {
+        let src = "asm (\"=r\"(x)) nop; } func g() > void { var b:int = 2; }";
+        let (result, errors) = lex(src, SourceKey::default());
+        assert!(errors.is_empty());
+        assert!(!result.iter().any(|token| matches!(token, Token::AsmBody(_))));
+        assert_eq!(result.iter().filter(|token| matches!(token, Token::LBrace(_))).count(), 1);
+
+        let (result, errors) = lex("asm (\"=r\"(x) : \"r\"(y)) { nop }", SourceKey::default());
+        assert!(errors.is_empty());
+        assert!(matches!(result.last(), Some(Token::AsmBody(Spanned(_, body))) if body == " nop "));
+    }
+
+    #[test]
+    fn lex_past_invalid_characters() {
+        let (result, errors) = lex("a @ b # c", SourceKey::default());
+        assert_eq!(result.len(), 3);
+        assert_eq!(
+            errors.iter().map(|e| (e.kind, e.location.span().clone())).collect::<Vec<_>>(),
+            [(LexerErrorKind::InvalidToken('@'), 2..3), (LexerErrorKind::InvalidToken('#'), 6..7)]
+        );
+
+        let report = errors[0].clone().into_report();
+        assert_eq!(report.title(), "invalid token");
+        assert_eq!(report.description(), Some("unexpected character `@`"));
+
+        let (_, errors) = lex("asm { nop", SourceKey::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexerErrorKind::UnterminatedAsmBlock);
+        assert_eq!(errors[0].location.span(), &(4..5));
+    }
+
+    #[test]
+    fn lex_number_literals() {
+        let (result, errors) = lex("0x1F 0b1010 0o17 1_000 4294967295", SourceKey::default());
+        assert!(errors.is_empty());
+        let values: Vec<_> = result
+            .iter()
+            .map(|token| match token {
+                Token::Number(Spanned(_, literal)) => literal.value,
+                other => panic!("expected a number, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(values, [0x1F, 0b1010, 0o17, 1000, u32::MAX]);
+        assert!(matches!(&result[3], Token::Number(Spanned(span, literal)) if literal.text == "1_000" && *span == (17..22)));
+
+        let (result, errors) = lex("12abc 0x 0b102 4294967296 7", SourceKey::default());
+        assert_eq!(result.len(), 5);
+        assert_eq!(
+            errors.iter().map(|e| (e.kind, e.location.span().clone())).collect::<Vec<_>>(),
+            [
+                (LexerErrorKind::MalformedNumber, 0..5),
+                (LexerErrorKind::MalformedNumber, 6..8),
+                (LexerErrorKind::MalformedNumber, 9..14),
+                (LexerErrorKind::NumberOverflow, 15..25),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_string_and_char_literals() {
+        let src = r#""a\tb\r\n\x41\\\"\0\xffé" 'a' '\n' '\xff' '\''"#;
+        let (result, errors) = lex(src, SourceKey::default());
+        assert!(errors.is_empty(), "{errors:?}");
+
+        let Token::String(Spanned(span, value)) = &result[0] else {
+            panic!("expected a string, got {:?}", result[0]);
+        };
+        assert_eq!(value, b"a\tb\r\nA\\\"\0\xff\xc3\xa9");
+        assert_eq!(&src[span.clone()], r#""a\tb\r\n\x41\\\"\0\xffé""#);
+
+        let chars: Vec<_> = result[1..]
+            .iter()
+            .map(|token| match token {
+                Token::Char(Spanned(span, value)) => (&src[span.clone()], *value),
+                other => panic!("expected a character, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(chars, [("'a'", b'a'), ("'\\n'", b'\n'), ("'\\xff'", 0xff), ("'\\''", b'\'')]);
+    }
+
+    #[test]
+    fn report_bad_string_and_char_literals() {
+        let src = r#""bad \q \xfg" 'ab' '' 'é' x "open"#;
+        let (result, errors) = lex(src, SourceKey::default());
+        assert!(matches!(&result[0], Token::String(Spanned(_, value)) if value == b"bad  g"));
+        // Closed literals still yield a token so the parser does not trip over them
+        assert!(result[1..4].iter().all(|token| matches!(token, Token::Char(Spanned(_, 0)))));
+        assert!(matches!(&result[4], Token::Identifier(_)));
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.kind, &src[e.location.span().clone()]))
+                .collect::<Vec<_>>(),
+            [
+                (LexerErrorKind::InvalidEscape, "\\q"),
+                (LexerErrorKind::InvalidEscape, "\\xf"),
+                (LexerErrorKind::InvalidCharLiteral, "'ab'"),
+                (LexerErrorKind::InvalidCharLiteral, "''"),
+                (LexerErrorKind::InvalidCharLiteral, "'é'"),
+                (LexerErrorKind::UnterminatedString, "\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        let src = "\"héllo\" ünit x";
+        let (result, errors) = lex(src, SourceKey::default());
+        assert_eq!(result[0].span(), 0..8);
+        assert_eq!(result[1].span(), 11..14);
+        assert_eq!(result[2].span(), 15..16);
+        assert_eq!(errors[0].kind, LexerErrorKind::InvalidToken('ü'));
+        assert_eq!(&src[errors[0].location.span().clone()], "ü");
+    }
+
+    #[test]
+    fn lex_comments() {
+        let src = "/// Adds\n///\n//// plain\nfunc /* a /* nested */ block */ f // line\n/* open /* */";
+        let (result, errors) = lex(src, SourceKey::default());
+        let docs: Vec<_> = result
+            .iter()
+            .filter_map(|token| match token {
+                Token::DocComment(Spanned(_, text)) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(docs, ["Adds", ""]);
+        assert_eq!(
+            result.iter().skip(2).map(Token::kind).collect::<Vec<_>>(),
+            [TokenKind::Function, TokenKind::Identifier]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexerErrorKind::UnterminatedComment);
+        assert_eq!(&src[errors[0].location.span().clone()], "/*");
+    }
+
+    #[test]
+    fn lossless_tokens_cover_the_source() {
+        let src = std::fs::read_to_string("../syntax/syntax2.se").unwrap();
+        let (result, errors) = lex_lossless(&src, SourceKey::default());
+        assert!(errors.is_empty());
+        assert!(result.iter().any(Token::is_trivia));
+        let text: String = result.iter().map(|token| &src[token.span()]).collect();
+        assert_eq!(text, src);
+    }
+
+    #[test]
+    fn lex_identifiers() {
+        let src = "my_var _x9 r#if if func r#func x2y as ask";
+        let (result, errors) = lex(src, SourceKey::default());
+        assert!(errors.is_empty());
+        let names: Vec<_> = result
+            .iter()
+            .map(|token| match token {
+                Token::Identifier(Spanned(span, name)) => format!("{name}@{}", &src[span.clone()]),
+                other => other.kind().to_string(),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "my_var@my_var",
+                "_x9@_x9",
+                "if@r#if",
+                "`if`",
+                "`func`",
+                "func@r#func",
+                "x2y@x2y",
+                "`as`",
+                "ask@ask",
+            ]
+        );
+
+        for (keyword, kind) in KEYWORDS {
+            let (result, _) = lex(keyword, SourceKey::default());
+            assert!(matches!(&result[..], [token] if token.kind() == kind), "{keyword}");
+        }
+
+        let (_, errors) = lex("naïve", SourceKey::default());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexerErrorKind::InvalidToken('ï'));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use lexer::{Span, Spanned, Token, TokenKind};
+
+/// Position in a `TokenCursor` that can be restored with `TokenCursor::rewind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
+/// Forward cursor over the lexer output
+#[derive(Debug, Clone)]
+pub struct TokenCursor {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Doc comments keyed by the index of the token they precede
+    docs: HashMap<usize, String>,
+}
+
+impl TokenCursor {
+    /// Doc comments and trivia are taken out of the stream, consecutive `///`
+    /// lines are joined and kept for the token that follows them
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let mut docs: HashMap<usize, String> = HashMap::new();
+        let mut kept = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match token {
+                Token::DocComment(Spanned(_, line)) => {
+                    let doc = docs.entry(kept.len()).or_default();
+                    if !doc.is_empty() {
+                        doc.push('\n');
+                    }
+                    doc.push_str(&line);
+                }
+                token if token.is_trivia() => {}
+                token => kept.push(token),
+            }
+        }
+        Self {
+            tokens: kept,
+            position: 0,
+            docs,
+        }
+    }
+
+    /// Doc comment written right before the next token
+    pub fn doc(&self) -> Option<&str> {
+        self.docs.get(&self.position).map(String::as_str)
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    /// Looks `n` tokens ahead without consuming anything, `peek_nth(0)` is
+    /// the next token
+    pub fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.position + n)
+    }
+
+    pub fn peek_kind(&self) -> Option<TokenKind> {
+        self.peek().map(Token::kind)
+    }
+
+    /// Whether the next token is of the given kind
+    pub fn at(&self, kind: TokenKind) -> bool {
+        self.peek_kind() == Some(kind)
+    }
+
+    /// Consumes the next token only if it is of the given kind
+    pub fn eat(&mut self, kind: TokenKind) -> Option<Token> {
+        if self.at(kind) {
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    /// Span of the next token, or an empty span right after the last one
+    /// once the stream is exhausted
+    pub fn span(&self) -> Span {
+        match self.peek() {
+            Some(t) => t.span(),
+            None => {
+                let end = self.tokens.last().map_or(0, |t| t.span().end);
+                end..end
+            }
+        }
+    }
+
+    /// End of the last consumed token, 0 before the first one
+    pub fn last_end(&self) -> usize {
+        self.position
+            .checked_sub(1)
+            .and_then(|last| self.tokens.get(last))
+            .map_or(0, |t| t.span().end)
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.position)
+    }
+
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        self.position = checkpoint.0;
+    }
+}
+
+impl Iterator for TokenCursor {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reports::sourcemap::SourceKey;
+
+    use super::*;
+
+    #[test]
+    fn cursor_lookahead_and_rewind() {
+        let mut cursor = TokenCursor::new(lexer::lex("func f(a:int)", SourceKey::default()).0);
+        assert_eq!(cursor.peek_nth(1).map(Token::kind), Some(TokenKind::Identifier));
+        assert!(cursor.eat(TokenKind::Identifier).is_none());
+
+        let checkpoint = cursor.checkpoint();
+        assert!(cursor.eat(TokenKind::Function).is_some());
+        assert!(cursor.at(TokenKind::Identifier));
+        cursor.rewind(checkpoint);
+        assert!(cursor.at(TokenKind::Function));
+
+        assert_eq!(cursor.by_ref().count(), 7);
+        assert!(cursor.is_at_end());
+        assert_eq!(cursor.span(), 13..13);
+    }
+
+    #[test]
+    fn cursor_collects_doc_comments() {
+        let src = "/// Adds\n/// twice\nfunc f() /// stray\n";
+        let mut cursor = TokenCursor::new(lexer::lex_lossless(src, SourceKey::default()).0);
+        assert_eq!(cursor.doc(), Some("Adds\ntwice"));
+        cursor.next();
+        assert_eq!(cursor.doc(), None);
+        assert_eq!(cursor.by_ref().count(), 3);
+        assert_eq!(cursor.doc(), Some("stray"));
+    }
+}
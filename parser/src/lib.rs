@@ -1,99 +1,1045 @@
+pub mod cursor;
+pub mod print;
 
-use lexer::Token;
+use ast::{AsmOperand, Expr, Function, InlineAsm, Namespace, NumberLiteral, Parameter, Path, Spanned, Type};
+use cursor::TokenCursor;
+use lexer::{Token, TokenKind};
+use reports::{sourcemap::SourceKey, IntoReport, Level, Location, Report, ReportContext};
 
-pub enum ParserError {
+/// Binding power of prefix operators, `-a ** b` parses as `-(a ** b)`
+const PREFIX_BP: u8 = 8;
+
+/// Left binding power of `as`, `-a as i32` parses as `(-a) as i32` and
+/// `a * b as i32` as `a * (b as i32)`
+const CAST_BP: u8 = 7;
+
+/// Left and right binding powers of an infix operator, a higher left power
+/// on the right side makes the operator right associative
+fn infix_binding_power(token: &Token) -> Option<(u8, u8)> {
+    match token {
+        Token::Eqq(_) | Token::NotEq(_) | Token::Greater(_) | Token::Less(_) => Some((1, 2)),
+        Token::Plus(_) | Token::Minus(_) => Some((3, 4)),
+        Token::Mul(_) | Token::Div(_) => Some((5, 6)),
+        Token::Pow(_) => Some((10, 9)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParserErrorKind {
     FailedTopLevel,
-    FailedFunction,
-    ConversionError,
     UnexpectedToken,
+    UnexpectedEof,
+    Expected(TokenKind),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParserError {
+    pub kind: ParserErrorKind,
+    pub location: Location,
+}
+
+impl IntoReport for ParserError {
+    fn into_report(self) -> Report {
+        let title = match self.kind {
+            ParserErrorKind::FailedTopLevel => "expected a function or namespace definition",
+            ParserErrorKind::UnexpectedToken | ParserErrorKind::Expected(_) => "unexpected token",
+            ParserErrorKind::UnexpectedEof => "unexpected end of file",
+        };
+        let description = match self.kind {
+            ParserErrorKind::Expected(kind) => Some(format!("expected {kind}")),
+            _ => None,
+        };
+        Report::new(
+            Level::Error,
+            self.location.span().clone(),
+            self.location.source_key(),
+            title,
+            description,
+        )
+    }
 }
 
 pub struct Parser {
-    source: Vec<Token>,
+    cursor: TokenCursor,
+    source_key: SourceKey,
+    errors: Vec<ParserError>,
     ast: ast::Ast,
 }
 
 impl Parser {
-    pub fn new(source: Vec<Token>) -> Self {
+    pub fn new(source: Vec<Token>, source_key: SourceKey) -> Self {
         Self {
-            source,
+            cursor: TokenCursor::new(source),
+            source_key,
+            errors: Vec::new(),
             ast: ast::Ast {
                 root: Vec::new()
             },
         }
     }
 
-    fn next(&mut self) -> Option<Token> {
-        match self.source.pop() {
-            Some(t) => Some(t),
-            None => None,
+    /// Builds an error located at the current token
+    fn error(&self, kind: ParserErrorKind) -> ParserError {
+        ParserError {
+            kind,
+            location: Location::new(self.cursor.span(), self.source_key),
         }
     }
 
+    /// Tags `value` with the source from `start` up to the last consumed token
+    fn spanned<T>(&self, start: usize, value: T) -> Spanned<T> {
+        Spanned(start..self.cursor.last_end().max(start), value)
+    }
+
+    fn start(&self) -> usize {
+        self.cursor.span().start
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.cursor.next()
+    }
+
     fn peek(&self) -> Result<&Token, ParserError> {
-        match self.source.last() {
+        match self.cursor.peek() {
             Some(t) => Ok(t),
-            None => Err(ParserError::UnexpectedToken),
-        }
-    }
-
-    fn expect<T: Copy + std::convert::From<std::string::String> + std::convert::From<i32>> (&mut self, token: Token) -> Result<T, ParserError> {
-        match self.next() {
-            Some(t) => {
-                if t == token {
-                    match t {
-                        Token::Number(n) => {
-                            return Ok( n.1.into() )
-                        }
-                        Token::Identifier(s) => {
-                            return Ok( s.1.into() )
-                        }
-                        _ => Err(ParserError::ConversionError),
-                    }
+            None => Err(self.error(ParserErrorKind::UnexpectedEof)),
+        }
+    }
+
+    /// Consumes the next token if it is of the given kind, a mismatching
+    /// token is left in place
+    fn expect(&mut self, kind: TokenKind) -> Result<(), ParserError> {
+        if self.peek()?.kind() == kind {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.error(ParserErrorKind::Expected(kind)))
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<String, ParserError> {
+        match self.peek()? {
+            Token::Identifier(ident) => {
+                let name = ident.1.clone();
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.error(ParserErrorKind::UnexpectedToken)),
+        }
+    }
+
+    /// Consumes the `,` between two list elements, returns `false` once the
+    /// closing `)` has been consumed
+    fn list_separator(&mut self) -> Result<bool, ParserError> {
+        match self.peek()? {
+            Token::Comma(_) => {
+                self.next();
+                Ok(true)
+            }
+            Token::RParen(_) => {
+                self.next();
+                Ok(false)
+            }
+            _ => Err(self.error(ParserErrorKind::UnexpectedToken)),
+        }
+    }
+
+    /// Skips tokens up to a point where parsing can resume: past the next
+    /// `;`, or before the next `}`, `func` or `namespace`
+    fn synchronize(&mut self) {
+        while let Ok(tok) = self.peek() {
+            match tok {
+                Token::Semicolon(_) => {
+                    self.next();
+                    return;
+                }
+                Token::RBrace(_) | Token::Function(_) | Token::Namespace(_) => return,
+                _ => {
+                    self.next();
+                }
+            }
+        }
+    }
+
+    /// name | *type | [type; length]
+    fn parse_type_inner(&mut self) -> Result<Type, ParserError> {
+        match self.peek()? {
+            Token::Mul(_) => {
+                self.next();
+                Ok(Type::Pointer(Box::new(self.parse_type_inner()?)))
+            }
+            // `**T` lexes as a power operator
+            Token::Pow(_) => {
+                self.next();
+                let inner = self.parse_type_inner()?;
+                Ok(Type::Pointer(Box::new(Type::Pointer(Box::new(inner)))))
+            }
+            Token::LBracket(_) => {
+                self.next();
+                let element = self.parse_type_inner()?;
+                self.expect(TokenKind::Semicolon)?;
+                let len = match self.peek()? {
+                    Token::Number(lexer::Spanned(_, literal)) => literal.value as usize,
+                    _ => return Err(self.error(ParserErrorKind::Expected(TokenKind::Number))),
+                };
+                self.next();
+                self.expect(TokenKind::RBracket)?;
+                Ok(Type::Array(Box::new(element), len))
+            }
+            _ => {
+                let name = self.expect_identifier()?;
+                Ok(Type::builtin(&name).unwrap_or(Type::Named(name)))
+            }
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<Spanned<Type>, ParserError> {
+        let start = self.start();
+        let ty = self.parse_type_inner()?;
+        Ok(self.spanned(start, ty))
+    }
+
+    /// `name:type`
+    fn parse_parameter(&mut self) -> Result<Parameter, ParserError> {
+        let start = self.start();
+        let name = self.expect_identifier()?;
+        let name = self.spanned(start, name);
+        self.expect(TokenKind::Colon)?;
+        let ty = self.parse_type()?;
+        Ok((name, ty))
+    }
+
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, ParserError> {
+        let mut params = Vec::new();
+        self.expect(TokenKind::LParen)?;
+        if let Token::RParen(_) = self.peek()? {
+            self.next();
+            return Ok(params);
+        }
+        loop {
+            params.push(self.parse_parameter()?);
+            if !self.list_separator()? {
+                break;
+            }
+        }
+        Ok(params)
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Spanned<Expr>>, ParserError> {
+        let mut args = Vec::new();
+        self.expect(TokenKind::LParen)?;
+        if let Token::RParen(_) = self.peek()? {
+            self.next();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_expression()?);
+            if !self.list_separator()? {
+                break;
+            }
+        }
+        Ok(args)
+    }
+
+    /// Remaining `.member` segments of a path starting with `first`
+    fn parse_path(&mut self, here: bool, first: String) -> Result<Path, ParserError> {
+        let mut segments = vec![first];
+        while self.cursor.eat(TokenKind::Dot).is_some() {
+            segments.push(self.expect_identifier()?);
+        }
+        Ok(Path { here, segments })
+    }
+
+    fn parse_primary(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        match self.peek()? {
+            Token::Number(_)
+            | Token::Char(_)
+            | Token::String(_)
+            | Token::Identifier(_)
+            | Token::Here(_)
+            | Token::LParen(_) => {}
+            _ => return Err(self.error(ParserErrorKind::UnexpectedToken)),
+        }
+        let expr = match self.next() {
+            Some(Token::Number(lexer::Spanned(_, literal))) => Expr::Number(NumberLiteral {
+                value: literal.value.into(),
+                text: literal.text,
+            }),
+            Some(Token::Char(lexer::Spanned(_, value))) => Expr::Char(value),
+            Some(Token::String(lexer::Spanned(_, value))) => Expr::Str(value),
+            Some(Token::Identifier(lexer::Spanned(_, name))) => {
+                let mut path = self.parse_path(false, name)?;
+                if let Ok(Token::LParen(_)) = self.peek() {
+                    let args = self.parse_arguments()?;
+                    Expr::Call(path, args)
+                } else if path.segments.len() == 1 {
+                    Expr::Ident(path.segments.remove(0))
                 } else {
-                    Err(ParserError::UnexpectedToken)
+                    // Namespaces only hold functions
+                    return Err(self.error(ParserErrorKind::Expected(TokenKind::LParen)));
                 }
             }
-            None => Err(ParserError::UnexpectedToken),
+            Some(Token::Here(_)) => {
+                self.expect(TokenKind::Dot)?;
+                let first = self.expect_identifier()?;
+                let path = self.parse_path(true, first)?;
+                let args = self.parse_arguments()?;
+                Expr::Call(path, args)
+            }
+            _ => {
+                // The group spans its parentheses
+                let Spanned(_, expr) = self.parse_expression()?;
+                self.expect(TokenKind::RParen)?;
+                expr
+            }
+        };
+        Ok(self.spanned(start, expr))
+    }
+
+    fn parse_unary(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        let expr = match self.peek()? {
+            Token::Minus(_) => {
+                self.next();
+                Expr::Neg(Box::new(self.parse_binary(PREFIX_BP)?))
+            }
+            Token::Not(_) => {
+                self.next();
+                Expr::Not(Box::new(self.parse_binary(PREFIX_BP)?))
+            }
+            _ => return self.parse_primary(),
+        };
+        Ok(self.spanned(start, expr))
+    }
+
+    /// Precedence climbing over the infix operators binding at least as
+    /// tightly as `min_bp`
+    fn parse_binary(&mut self, min_bp: u8) -> Result<Spanned<Expr>, ParserError> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            if let Ok(Token::As(_)) = self.peek() {
+                if CAST_BP < min_bp {
+                    break;
+                }
+                self.next();
+                let ty = self.parse_type()?;
+                let span = lhs.0.start..ty.0.end;
+                lhs = Spanned(span, Expr::Cast(Box::new(lhs), ty));
+                continue;
+            }
+            let Some((left_bp, right_bp)) = self.peek().ok().and_then(infix_binding_power) else {
+                break;
+            };
+            if left_bp < min_bp {
+                break;
+            }
+            let op = self.peek()?.clone();
+            self.next();
+            let rhs = Box::new(self.parse_binary(right_bp)?);
+            let span = lhs.0.start..rhs.0.end;
+            let lhs_box = Box::new(lhs);
+
+            let expr = match op {
+                Token::Plus(_) => Expr::Add(lhs_box, rhs),
+                Token::Minus(_) => Expr::Sub(lhs_box, rhs),
+                Token::Mul(_) => Expr::Mul(lhs_box, rhs),
+                Token::Div(_) => Expr::Div(lhs_box, rhs),
+                Token::Pow(_) => Expr::Pow(lhs_box, rhs),
+                Token::Eqq(_) => Expr::Eq(lhs_box, rhs),
+                Token::NotEq(_) => Expr::NotEq(lhs_box, rhs),
+                Token::Greater(_) => Expr::Greater(lhs_box, rhs),
+                Token::Less(_) => Expr::Less(lhs_box, rhs),
+                _ => unreachable!("infix_binding_power only accepts binary operators"),
+            };
+            lhs = Spanned(span, expr);
+        }
+
+        Ok(lhs)
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        self.parse_binary(0)
+    }
+
+    /// var name:type = value;
+    fn parse_declaration(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        self.expect(TokenKind::Var)?;
+        let name = self.expect_identifier()?;
+        self.expect(TokenKind::Colon)?;
+        let ty = self.parse_type()?;
+        self.expect(TokenKind::Eq)?;
+        let value = self.parse_expression()?;
+        self.expect(TokenKind::Semicolon)?;
+
+        Ok(self.spanned(start, Expr::Decl(name, ty, Box::new(value))))
+    }
+
+    /// return value; | return;
+    fn parse_return(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        self.expect(TokenKind::Return)?;
+        if let Token::Semicolon(_) = self.peek()? {
+            let value = Spanned(self.cursor.span().start..self.cursor.span().start, Expr::Empty);
+            self.next();
+            return Ok(self.spanned(start, Expr::Return(Box::new(value))));
+        }
+        let value = self.parse_expression()?;
+        self.expect(TokenKind::Semicolon)?;
+
+        Ok(self.spanned(start, Expr::Return(Box::new(value))))
+    }
+
+    /// if cond then statement [else statement]
+    fn parse_if(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        self.expect(TokenKind::If)?;
+        let cond = self.parse_expression()?;
+        self.expect(TokenKind::Then)?;
+        let then = self.parse_statement()?;
+        let otherwise = if let Ok(Token::Else(_)) = self.peek() {
+            self.next();
+            self.parse_statement()?
+        } else {
+            Spanned(then.0.end..then.0.end, Expr::Empty)
+        };
+
+        Ok(self.spanned(start, Expr::If(Box::new(cond), Box::new(then), Box::new(otherwise))))
+    }
+
+    fn expect_string(&mut self) -> Result<String, ParserError> {
+        match self.peek()? {
+            // Constraints and clobbers are plain text
+            Token::String(string) => {
+                let value = String::from_utf8_lossy(&string.1).into_owned();
+                self.next();
+                Ok(value)
+            }
+            _ => Err(self.error(ParserErrorKind::Expected(TokenKind::String))),
         }
     }
 
-    fn parse_function(&mut self) -> Result<(), ParserError> {
-        let x: &str = &*self.expect(Token::Function(0..0))?;
+    /// "constraint"(variable)
+    fn parse_asm_operand(&mut self) -> Result<AsmOperand, ParserError> {
+        let constraint = self.expect_string()?;
+        self.expect(TokenKind::LParen)?;
+        let variable = self.expect_identifier()?;
+        self.expect(TokenKind::RParen)?;
+        Ok(AsmOperand { constraint, variable })
+    }
 
-        Ok(())
+    /// Comma separated, possibly empty, section of an asm operand list
+    fn parse_asm_section<T>(
+        &mut self,
+        parse: fn(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<Vec<T>, ParserError> {
+        let mut section = Vec::new();
+        if matches!(self.peek()?, Token::Colon(_) | Token::RParen(_)) {
+            return Ok(section);
+        }
+        loop {
+            section.push(parse(self)?);
+            if self.cursor.eat(TokenKind::Comma).is_none() {
+                return Ok(section);
+            }
+        }
     }
 
-    fn parse_toplevel(&mut self) -> Result<(), ParserError> {
-        while let Some(tok) = self.next() {
-            match tok {
-                Token::Function(_) => {
-                    self.parse_function();
+    /// asm [(outputs [: inputs [: clobbers]])] { raw text }
+    fn parse_asm(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        self.expect(TokenKind::Asm)?;
+        let mut asm = InlineAsm::default();
+        if self.cursor.eat(TokenKind::LParen).is_some() {
+            asm.outputs = self.parse_asm_section(Self::parse_asm_operand)?;
+            if self.cursor.eat(TokenKind::Colon).is_some() {
+                asm.inputs = self.parse_asm_section(Self::parse_asm_operand)?;
+                if self.cursor.eat(TokenKind::Colon).is_some() {
+                    asm.clobbers = self.parse_asm_section(Self::expect_string)?;
                 }
-                _ => {
-                    return Err(ParserError::FailedTopLevel);
+            }
+            self.expect(TokenKind::RParen)?;
+        }
+        match self.peek()? {
+            Token::AsmBody(body) => {
+                asm.text = body.1.clone();
+                self.next();
+                Ok(self.spanned(start, Expr::InlineAsm(asm)))
+            }
+            _ => Err(self.error(ParserErrorKind::Expected(TokenKind::AsmBody))),
+        }
+    }
+
+    /// name = value; | value;
+    fn parse_expression_statement(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        let expr = match (self.peek()?, self.cursor.peek_nth(1)) {
+            (Token::Identifier(_), Some(Token::Eq(_))) => {
+                let name = self.expect_identifier()?;
+                self.expect(TokenKind::Eq)?;
+                let value = self.parse_expression()?;
+                Spanned(start..value.0.end, Expr::Assign(name, Box::new(value)))
+            }
+            _ => self.parse_expression()?,
+        };
+        self.expect(TokenKind::Semicolon)?;
+
+        Ok(expr)
+    }
+
+    fn parse_statement(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        match self.peek()? {
+            Token::Var(_) => self.parse_declaration(),
+            Token::Return(_) => self.parse_return(),
+            Token::If(_) => self.parse_if(),
+            Token::Asm(_) => self.parse_asm(),
+            Token::LBrace(_) => self.parse_block(),
+            Token::Semicolon(_) => {
+                self.next();
+                Ok(self.spanned(start, Expr::Empty))
+            }
+            _ => self.parse_expression_statement(),
+        }
+    }
+
+    /// Statements failing to parse are recorded and skipped, so the block
+    /// keeps every statement that could be recovered
+    fn parse_block(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        self.expect(TokenKind::LBrace)?;
+        let mut stats = Vec::new();
+        loop {
+            match self.peek()? {
+                Token::RBrace(_) => break,
+                // Ran into the next declaration, the brace is missing
+                Token::Function(_) | Token::Namespace(_) => {
+                    return Err(self.error(ParserErrorKind::UnexpectedToken))
                 }
+                _ => {}
+            }
+            match self.parse_statement() {
+                Ok(stat) => stats.push(stat),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
+            }
+        }
+        self.expect(TokenKind::RBrace)?;
+
+        Ok(self.spanned(start, Expr::Block(stats)))
+    }
+
+    /// func name(a:type, ...) > type { ... } or the prototype
+    /// func name(a:type, ...) > type;
+    fn parse_function(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        let doc = self.cursor.doc().map(str::to_string);
+        self.expect(TokenKind::Function)?;
+        let name = self.expect_identifier()?;
+        let params = self.parse_parameters()?;
+        self.expect(TokenKind::Greater)?;
+        let ret = self.parse_type()?;
+        let body = match self.peek()? {
+            Token::Semicolon(_) => {
+                self.next();
+                None
             }
+            _ => Some(Box::new(self.parse_block()?)),
+        };
+
+        Ok(self.spanned(
+            start,
+            Expr::Function(Function {
+                name,
+                ret,
+                params,
+                body,
+                doc,
+            }),
+        ))
+    }
+
+    /// namespace name { items }
+    fn parse_namespace(&mut self) -> Result<Spanned<Expr>, ParserError> {
+        let start = self.start();
+        let doc = self.cursor.doc().map(str::to_string);
+        self.expect(TokenKind::Namespace)?;
+        let name = self.expect_identifier()?;
+        self.expect(TokenKind::LBrace)?;
+        let body = self.parse_items(true);
+        // Keep what was parsed even if the namespace is left open
+        if let Err(err) = self.expect(TokenKind::RBrace) {
+            self.errors.push(err);
         }
 
-        Ok(())
+        Ok(self.spanned(start, Expr::Namespace(Namespace { name, items: body, doc })))
+    }
+
+    /// Parses functions and namespaces up to the end of the stream, or up to
+    /// the closing `}` of the enclosing namespace when `nested`
+    fn parse_items(&mut self, nested: bool) -> Vec<Spanned<Expr>> {
+        let mut items = Vec::new();
+        while let Ok(tok) = self.peek() {
+            let result = match tok {
+                Token::Function(_) => self.parse_function(),
+                Token::Namespace(_) => self.parse_namespace(),
+                Token::RBrace(_) if nested => break,
+                _ => {
+                    let err = self.error(ParserErrorKind::FailedTopLevel);
+                    // Always make progress, `synchronize` stops before `}`
+                    self.next();
+                    Err(err)
+                }
+            };
+            match result {
+                Ok(item) => items.push(item),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronize();
+                }
+            }
+        }
+        items
     }
 
-    pub fn process(&mut self) -> Result<(), ParserError> {
-        self.parse_toplevel()
+    /// Parses the whole token stream, every syntax error is added to
+    /// `reports` and the returned `Ast` holds the definitions that parsed
+    pub fn process(mut self, reports: &mut ReportContext) -> ast::Ast {
+        self.ast.root = self.parse_items(false);
+        reports.extend(self.errors.into_iter().map(IntoReport::into_report));
+        self.ast
     }
-    
+
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn tokens(src: &str) -> Vec<Token> {
+        let (tokens, errors) = lexer::lex(src, SourceKey::default());
+        assert!(errors.is_empty(), "{errors:?}");
+        tokens
+    }
+
     #[test]
     fn parser_test0() {
         let src = std::fs::read_to_string("../syntax/syntax0.se").unwrap();
-        let tokens = lexer::lex(&src);
-        let mut parser = Parser::new(tokens);
-        let _ = parser.process();
+        let tokens = tokens(&src);
+        let mut reports = ReportContext::default();
+        let _ = Parser::new(tokens, SourceKey::default()).process(&mut reports);
+    }
+
+    #[test]
+    fn parse_the_comment_corpus() {
+        let src = std::fs::read_to_string("../syntax/syntax2.se").unwrap();
+        let tokens = tokens(&src);
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens, SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+        assert!(matches!(&ast.root[0].1, Expr::Function(func) if func.doc.as_deref() == Some("Sum of both operands")));
+        let Expr::Namespace(namespace) = &ast.root[1].1 else {
+            panic!("expected a namespace, got {:?}", ast.root[1]);
+        };
+        assert_eq!(namespace.doc.as_deref(), Some("Things one would\nrather not repeat"));
+        assert!(matches!(&namespace.items[0].1, Expr::Function(func) if func.doc.is_none()));
+    }
+
+    fn parse_expr(src: &str) -> Expr {
+        let mut parser = Parser::new(tokens(src), SourceKey::default());
+        parser.parse_expression().unwrap().1
+    }
+
+    #[test]
+    fn parse_expression_precedence() {
+        let expr = parse_expr("a+b*2 == -c**2**3");
+        let Expr::Eq(lhs, rhs) = expr else {
+            panic!("expected a comparison, got {expr:?}");
+        };
+        let Expr::Add(_, product) = lhs.1 else {
+            panic!("expected an addition");
+        };
+        assert!(matches!(product.1, Expr::Mul(_, _)));
+        // Unary minus binds looser than `**`, which is right associative
+        let Expr::Neg(power) = rhs.1 else {
+            panic!("expected a negation");
+        };
+        let Expr::Pow(base, exponent) = power.1 else {
+            panic!("expected a power");
+        };
+        assert!(matches!(base.1, Expr::Ident(_)));
+        assert!(matches!(exponent.1, Expr::Pow(_, _)));
+    }
+
+    #[test]
+    fn parse_literals() {
+        let expr = parse_expr(r#"send("hi\r\n", 'a')"#);
+        let Expr::Call(_, args) = expr else {
+            panic!("expected a call, got {expr:?}");
+        };
+        assert!(matches!(&args[0].1, Expr::Str(value) if value == b"hi\r\n"));
+        assert!(matches!(args[1].1, Expr::Char(b'a')));
+
+        let literal = NumberLiteral {
+            value: 31,
+            text: "0x1F".into(),
+        };
+        assert_eq!(parse_expr("0x1F"), Expr::Number(literal));
+    }
+
+    #[test]
+    fn parse_casts() {
+        let src = "-a as i32 * b as u8 as *char + c ** d as u8";
+        let mut parser = Parser::new(tokens(src), SourceKey::default());
+        let expr = parser.parse_expression().unwrap();
+        let Expr::Add(product, cast) = expr.1 else {
+            panic!("expected a sum, got {expr:?}");
+        };
+        let Expr::Mul(lhs, rhs) = product.1 else {
+            panic!("expected a product");
+        };
+        assert!(matches!(&lhs.1, Expr::Cast(value, Spanned(_, Type::I32)) if matches!(value.1, Expr::Neg(_))));
+        let Expr::Cast(inner, Spanned(span, Type::Pointer(_))) = &rhs.1 else {
+            panic!("expected a pointer cast, got {rhs:?}");
+        };
+        assert_eq!(&src[span.clone()], "*char");
+        assert_eq!(&src[rhs.0.clone()], "b as u8 as *char");
+        assert!(matches!(&inner.1, Expr::Cast(_, Spanned(_, Type::U8))));
+        assert!(matches!(&cast.1, Expr::Cast(value, Spanned(_, Type::U8)) if matches!(value.1, Expr::Pow(_, _))));
+    }
+
+    #[test]
+    fn parse_expression_grouping_and_calls() {
+        let expr = parse_expr("!(a - b - c) * add(1, x)");
+        let Expr::Mul(lhs, rhs) = expr else {
+            panic!("expected a product, got {expr:?}");
+        };
+        let Expr::Not(inner) = lhs.1 else {
+            panic!("expected a negation");
+        };
+        // Subtraction is left associative
+        let Expr::Sub(first, _) = inner.1 else {
+            panic!("expected a subtraction");
+        };
+        assert!(matches!(first.1, Expr::Sub(_, _)));
+        match rhs.1 {
+            Expr::Call(path, args) => {
+                assert_eq!(path, Path::from("add"));
+                assert_eq!(args.len(), 2);
+            }
+            other => panic!("expected a call, got {other:?}"),
+        }
+    }
+
+    fn parse_stat(src: &str) -> Expr {
+        let mut parser = Parser::new(tokens(src), SourceKey::default());
+        parser.parse_statement().unwrap().1
+    }
+
+    #[test]
+    fn parse_statements() {
+        let block = parse_stat("{ var x:int = 1 + 2; x = x * 3; add(x, 1); return; }");
+        let Expr::Block(stats) = block else {
+            panic!("expected a block, got {block:?}");
+        };
+        assert!(matches!(&stats[0].1, Expr::Decl(name, Spanned(_, Type::I16), value) if name == "x" && matches!(value.1, Expr::Add(_, _))));
+        assert!(matches!(&stats[1].1, Expr::Assign(name, value) if name == "x" && matches!(value.1, Expr::Mul(_, _))));
+        assert!(matches!(&stats[2].1, Expr::Call(path, args) if path.name() == "add" && args.len() == 2));
+        assert!(matches!(&stats[3].1, Expr::Return(value) if matches!(value.1, Expr::Empty)));
+    }
+
+    #[test]
+    fn node_spans() {
+        let src = "{ total = add(a, 1) + (b * 2); if x then return; }";
+        let mut parser = Parser::new(tokens(src), SourceKey::default());
+        let block = parser.parse_statement().unwrap();
+        assert_eq!(block.0, 0..src.len());
+        let Expr::Block(stats) = block.1 else {
+            panic!("expected a block");
+        };
+        let text = |span: &ast::Span| &src[span.clone()];
+        let Expr::Assign(_, value) = &stats[0].1 else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(text(&stats[0].0), "total = add(a, 1) + (b * 2)");
+        let Expr::Add(call, group) = &value.1 else {
+            panic!("expected an addition");
+        };
+        assert_eq!(text(&call.0), "add(a, 1)");
+        assert_eq!(text(&group.0), "(b * 2)");
+
+        let Expr::If(cond, then, otherwise) = &stats[1].1 else {
+            panic!("expected an if");
+        };
+        assert_eq!(text(&stats[1].0), "if x then return;");
+        assert_eq!(text(&cond.0), "x");
+        assert_eq!(text(&then.0), "return;");
+        assert_eq!(otherwise.0, 48..48);
+    }
+
+    #[test]
+    fn parse_types() {
+        let src = "var a:u8 = 0; var b:*char = 0; var c:[**i32; 4] = 0; var d:Point = 0; var e:bool = 0;";
+        let mut parser = Parser::new(tokens(src), SourceKey::default());
+        let types: Vec<_> = std::iter::from_fn(|| parser.parse_statement().ok())
+            .map(|stat| match stat.1 {
+                Expr::Decl(_, Spanned(span, ty), _) => (src[span].to_string(), ty),
+                other => panic!("expected a declaration, got {other:?}"),
+            })
+            .collect();
+        let pointer = |ty| Type::Pointer(Box::new(ty));
+        assert_eq!(
+            types,
+            [
+                ("u8".into(), Type::U8),
+                ("*char".into(), pointer(Type::U8)),
+                ("[**i32; 4]".into(), Type::Array(Box::new(pointer(pointer(Type::I32))), 4)),
+                ("Point".into(), Type::Named("Point".into())),
+                ("bool".into(), Type::Bool),
+            ]
+        );
+        assert_eq!(types[2].1.to_string(), "[**i32; 4]");
+    }
+
+    #[test]
+    fn parse_if_statement() {
+        let stat = parse_stat("if (1 != 2) then { return 0; } else { return 1; }");
+        let Expr::If(cond, then, otherwise) = stat else {
+            panic!("expected an if, got {stat:?}");
+        };
+        assert!(matches!(cond.1, Expr::NotEq(_, _)));
+        assert!(matches!(then.1, Expr::Block(_)));
+        assert!(matches!(otherwise.1, Expr::Block(_)));
+
+        let stat = parse_stat("if x then return x;");
+        assert!(matches!(stat, Expr::If(_, then, otherwise) if matches!(then.1, Expr::Return(_)) && matches!(otherwise.1, Expr::Empty)));
+    }
+
+    #[test]
+    fn parse_inline_asm() {
+        let stat = parse_stat("asm {\n    nop\n    sleep\n}");
+        assert!(matches!(stat, Expr::InlineAsm(asm) if asm.text == "\n    nop\n    sleep\n" && !asm.has_operands()));
+
+        let stat = parse_stat(r#"asm ("=r"(x), "+r"(y) : "r"(z) : "r0", "r1") { mul %1, %2 }"#);
+        let Expr::InlineAsm(asm) = stat else {
+            panic!("expected inline asm, got {stat:?}");
+        };
+        let operand = |constraint: &str, variable: &str| AsmOperand {
+            constraint: constraint.into(),
+            variable: variable.into(),
+        };
+        assert_eq!(asm.outputs, [operand("=r", "x"), operand("+r", "y")]);
+        assert_eq!(asm.inputs, [operand("r", "z")]);
+        assert_eq!(asm.clobbers, ["r0", "r1"]);
+        assert_eq!(asm.text, " mul %1, %2 ");
+
+        let stat = parse_stat(r#"asm (: "r"(z)) { push %0 }"#);
+        assert!(matches!(stat, Expr::InlineAsm(asm) if asm.outputs.is_empty() && asm.inputs.len() == 1));
+    }
+
+    #[test]
+    fn parse_function_definition() {
+        let tokens = tokens("func add(a:int, b:int) > int { return a+b; }");
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens, SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports());
+        assert_eq!(ast.root.len(), 1);
+        match &ast.root[0].1 {
+            Expr::Function(Function { name, ret, params, body, doc }) => {
+                assert_eq!(name, "add");
+                assert!(matches!(ret, Spanned(span, Type::I16) if *span == (25..28)));
+                assert!(doc.is_none());
+                let params: Vec<_> = params.iter().map(|(name, ty)| (name.1.as_str(), &ty.1)).collect();
+                assert_eq!(params, [("a", &Type::I16), ("b", &Type::I16)]);
+                let Some(Spanned(_, Expr::Block(stats))) = body.as_deref() else {
+                    panic!("expected a block body");
+                };
+                assert!(matches!(&stats[..], [Spanned(_, Expr::Return(value))] if matches!(value.1, Expr::Add(_, _))));
+            }
+            other => panic!("expected a function, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_prototypes() {
+        let src = "/// Defined in asm\nfunc putc(c: char) > void;\nnamespace io { func get() > u8; }";
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens(src), SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+        let Spanned(span, Expr::Function(func)) = &ast.root[0] else {
+            panic!("expected a prototype");
+        };
+        assert_eq!(&src[span.clone()], "func putc(c: char) > void;");
+        assert!(func.body.is_none());
+        assert_eq!(func.doc.as_deref(), Some("Defined in asm"));
+        let Expr::Namespace(namespace) = &ast.root[1].1 else {
+            panic!("expected a namespace");
+        };
+        assert!(matches!(&namespace.items[0].1, Expr::Function(func) if func.body.is_none()));
+
+        let mut reports = ReportContext::default();
+        Parser::new(tokens("func f() > void"), SourceKey::default()).process(&mut reports);
+        assert_eq!(reports[0].title(), "unexpected end of file");
+    }
+
+    #[test]
+    fn recover_from_multiple_errors() {
+        let src = "func a() > int { var x:int = ; return 1; }\n\
+                   ; func b() > int { return 2 }\n\
+                   func c() > int { return 3; }";
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens(src), SourceKey::default()).process(&mut reports);
+
+        let titles: Vec<_> = reports.iter().map(|r| r.title().to_string()).collect();
+        assert_eq!(
+            titles,
+            ["unexpected token", "expected a function or namespace definition", "unexpected token"]
+        );
+        // The initializer's `;` is the first offending token
+        assert_eq!(&src[reports[0].span().clone()], ";");
+        assert_eq!(reports[2].description(), Some("expected `;`"));
+        assert!(reports.has_errors());
+
+        let names: Vec<_> = ast
+            .root
+            .iter()
+            .map(|f| match &f.1 {
+                Expr::Function(func) => func.name.as_str(),
+                other => panic!("expected a function, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        // The statement after the broken declaration is kept
+        let Expr::Function(func) = &ast.root[0].1 else { unreachable!() };
+        assert!(matches!(func.body.as_deref(), Some(Spanned(_, Expr::Block(stats))) if stats.len() == 1));
+    }
+
+    #[test]
+    fn report_unexpected_eof() {
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens("func a() > int { return 1;"), SourceKey::default())
+            .process(&mut reports);
+        assert!(ast.root.is_empty());
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].title(), "unexpected end of file");
+        assert_eq!(reports[0].span(), &(26..26));
+    }
+
+    #[test]
+    fn attach_doc_comments() {
+        let src = "/// Math helpers\n\
+                   namespace M {\n\
+                       /// Adds\n\
+                       /// both\n\
+                       func add(a:int, b:int) > int { /// not attached\n return a+b; }\n\
+                       // plain\n\
+                       func id(a:int) > int { return a; }\n\
+                   }";
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens(src), SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports());
+
+        let [Spanned(_, Expr::Namespace(namespace))] = &ast.root[..] else {
+            panic!("expected a namespace, got {:?}", ast.root);
+        };
+        assert_eq!(namespace.doc.as_deref(), Some("Math helpers"));
+        let docs: Vec<_> = namespace
+            .items
+            .iter()
+            .map(|item| match &item.1 {
+                Expr::Function(func) => func.doc.as_deref(),
+                other => panic!("expected a function, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(docs, [Some("Adds\nboth"), None]);
+    }
+
+    #[test]
+    fn parse_namespaces() {
+        let src = "namespace LSpace {\n\
+                       func tst() > void { return; }\n\
+                       namespace Inner { func f() > void { here.tst(); LSpace.tst(); } }\n\
+                   }";
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens(src), SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports());
+
+        let [Spanned(_, Expr::Namespace(namespace))] = &ast.root[..] else {
+            panic!("expected a namespace, got {:?}", ast.root);
+        };
+        assert_eq!(namespace.name, "LSpace");
+        assert!(matches!(&namespace.items[0].1, Expr::Function(func) if func.name == "tst"));
+        let Expr::Namespace(inner) = &namespace.items[1].1 else {
+            panic!("expected a nested namespace");
+        };
+        assert_eq!(inner.name, "Inner");
+        let Expr::Function(func) = &inner.items[0].1 else {
+            panic!("expected a function");
+        };
+        let Some(Spanned(_, Expr::Block(stats))) = func.body.as_deref() else {
+            panic!("expected a block");
+        };
+        let paths: Vec<_> = stats
+            .iter()
+            .map(|stat| match &stat.1 {
+                Expr::Call(path, _) => path.to_string(),
+                other => panic!("expected a call, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(paths, ["here.tst", "LSpace.tst"]);
+    }
+
+    /// Zeroes every span so that trees parsed from different text compare
+    struct ClearSpans;
+
+    impl ast::fold::Fold for ClearSpans {
+        fn fold_expr(&mut self, expr: Spanned<Expr>) -> Spanned<Expr> {
+            let Spanned(_, expr) = ast::fold::walk_expr(self, expr);
+            Spanned(0..0, expr)
+        }
+
+        fn fold_type(&mut self, ty: Spanned<Type>) -> Spanned<Type> {
+            Spanned(0..0, ty.1)
+        }
+
+        fn fold_function(&mut self, func: ast::Function, span: &ast::Span) -> ast::Function {
+            let mut func = ast::fold::walk_function(self, func, span);
+            for (name, _) in &mut func.params {
+                name.0 = 0..0;
+            }
+            func
+        }
+    }
+
+    fn parse_source(src: &str) -> ast::Ast {
+        let mut reports = ReportContext::default();
+        let ast = Parser::new(tokens(src), SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports(), "{:?}\n{src}", &reports[..]);
+        ast::fold::Fold::fold_ast(&mut ClearSpans, ast)
+    }
+
+    #[test]
+    fn print_round_trips_the_corpus() {
+        let mut corpus: Vec<_> = std::fs::read_dir("../syntax")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "se"))
+            .collect();
+        corpus.sort();
+        assert!(corpus.len() >= 2);
+
+        for path in corpus {
+            let ast = parse_source(&std::fs::read_to_string(&path).unwrap());
+            let printed = print::print(&ast).unwrap();
+            let reparsed = parse_source(&printed);
+            assert_eq!(reparsed, ast, "{}:\n{printed}", path.display());
+            // Printing is canonical, a second pass changes nothing
+            assert_eq!(print::print(&reparsed).unwrap(), printed);
+        }
     }
 }
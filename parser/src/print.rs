@@ -0,0 +1,439 @@
+//! Canonical soel source for an `Ast`, parsing the output yields the same
+//! tree up to spans
+
+use ast::{AsmOperand, Ast, Expr, Function, InlineAsm, Namespace, Path, Spanned, Type};
+use lexer::KEYWORDS;
+
+const INDENT: &str = "    ";
+
+/// Binding strength of an expression, operands binding looser than their
+/// position requires are parenthesized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Prec {
+    Lowest,
+    Compare,
+    Sum,
+    Product,
+    Cast,
+    Prefix,
+    Power,
+    Primary,
+}
+
+fn precedence(expr: &Expr) -> Prec {
+    match expr {
+        Expr::Eq(..) | Expr::NotEq(..) | Expr::Greater(..) | Expr::Less(..) => Prec::Compare,
+        Expr::Add(..) | Expr::Sub(..) => Prec::Sum,
+        Expr::Mul(..) | Expr::Div(..) => Prec::Product,
+        Expr::Cast(..) => Prec::Cast,
+        Expr::Neg(_) | Expr::Not(_) => Prec::Prefix,
+        Expr::Pow(..) => Prec::Power,
+        _ => Prec::Primary,
+    }
+}
+
+/// Node that has no soel syntax, so printing it would not parse back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintError {
+    /// `while`, `for`, `break` and `continue`
+    Loop,
+}
+
+pub fn print(ast: &Ast) -> Result<String, PrintError> {
+    let mut printer = Printer::default();
+    printer.items(&ast.root);
+    printer.finish()
+}
+
+/// Single expression or statement, nested blocks start at indentation 0
+pub fn print_expr(expr: &Expr) -> Result<String, PrintError> {
+    let mut printer = Printer::default();
+    printer.statement(expr);
+    printer.finish()
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    depth: usize,
+    /// First unprintable node, the rest of the tree is still walked
+    error: Option<PrintError>,
+}
+
+impl Printer {
+    fn finish(self) -> Result<String, PrintError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.out),
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn ident(&mut self, name: &str) {
+        if KEYWORDS.iter().any(|(keyword, _)| *keyword == name) {
+            self.out.push_str("r#");
+        }
+        self.out.push_str(name);
+    }
+
+    fn items(&mut self, items: &[Spanned<Expr>]) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push('\n');
+                self.newline();
+            }
+            self.statement(&item.1);
+        }
+        if self.depth == 0 && !items.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    fn doc(&mut self, doc: &Option<String>) {
+        for line in doc.iter().flat_map(|doc| doc.split('\n')) {
+            self.out.push_str("///");
+            if !line.is_empty() {
+                self.out.push(' ');
+                self.out.push_str(line);
+            }
+            self.newline();
+        }
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.doc(&func.doc);
+        self.out.push_str("func ");
+        self.ident(&func.name);
+        self.out.push('(');
+        for (i, (name, ty)) in func.params.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.ident(&name.1);
+            self.out.push_str(": ");
+            self.ty(&ty.1);
+        }
+        self.out.push_str(") > ");
+        self.ty(&func.ret.1);
+        match &func.body {
+            Some(body) => {
+                self.out.push(' ');
+                self.statement(&body.1);
+            }
+            None => self.out.push(';'),
+        }
+    }
+
+    fn namespace(&mut self, namespace: &Namespace) {
+        self.doc(&namespace.doc);
+        self.out.push_str("namespace ");
+        self.ident(&namespace.name);
+        self.out.push_str(" {");
+        self.depth += 1;
+        self.newline();
+        self.items(&namespace.items);
+        self.depth -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Pointer(inner) => {
+                self.out.push('*');
+                self.ty(inner);
+            }
+            Type::Array(element, len) => {
+                self.out.push('[');
+                self.ty(element);
+                self.out.push_str(&format!("; {len}]"));
+            }
+            Type::Named(name) => self.ident(name),
+            builtin => self.out.push_str(&builtin.to_string()),
+        }
+    }
+
+    fn block(&mut self, stats: &[Spanned<Expr>]) {
+        if stats.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.depth += 1;
+        for stat in stats {
+            self.newline();
+            self.statement(&stat.1);
+        }
+        self.depth -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    fn statement(&mut self, stat: &Expr) {
+        match stat {
+            Expr::Function(func) => self.function(func),
+            Expr::Namespace(namespace) => self.namespace(namespace),
+            Expr::Block(stats) => self.block(stats),
+            Expr::Decl(name, ty, value) => {
+                self.out.push_str("var ");
+                self.ident(name);
+                self.out.push_str(": ");
+                self.ty(&ty.1);
+                self.out.push_str(" = ");
+                self.expr(&value.1, Prec::Lowest);
+                self.out.push(';');
+            }
+            Expr::Assign(name, value) => {
+                self.ident(name);
+                self.out.push_str(" = ");
+                self.expr(&value.1, Prec::Lowest);
+                self.out.push(';');
+            }
+            Expr::Return(value) => {
+                self.out.push_str("return");
+                if !matches!(value.1, Expr::Empty) {
+                    self.out.push(' ');
+                    self.expr(&value.1, Prec::Lowest);
+                }
+                self.out.push(';');
+            }
+            Expr::If(cond, then, otherwise) => {
+                self.out.push_str("if ");
+                self.expr(&cond.1, Prec::Lowest);
+                self.out.push_str(" then ");
+                self.statement(&then.1);
+                if !matches!(otherwise.1, Expr::Empty) {
+                    self.out.push_str(" else ");
+                    self.statement(&otherwise.1);
+                }
+            }
+            // Anything printed has to parse back
+            Expr::While(..) | Expr::For(..) | Expr::Break | Expr::Continue => {
+                self.error.get_or_insert(PrintError::Loop);
+            }
+            Expr::InlineAsm(asm) => self.inline_asm(asm),
+            Expr::Empty => self.out.push(';'),
+            expr => {
+                self.expr(expr, Prec::Lowest);
+                self.out.push(';');
+            }
+        }
+    }
+
+    fn operands(&mut self, operands: &[AsmOperand]) {
+        for (i, operand) in operands.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.string(operand.constraint.as_bytes());
+            self.out.push('(');
+            self.ident(&operand.variable);
+            self.out.push(')');
+        }
+    }
+
+    fn inline_asm(&mut self, asm: &InlineAsm) {
+        self.out.push_str("asm ");
+        if asm.has_operands() {
+            self.out.push('(');
+            self.operands(&asm.outputs);
+            if !asm.inputs.is_empty() || !asm.clobbers.is_empty() {
+                self.out.push_str(" : ");
+                self.operands(&asm.inputs);
+            }
+            if !asm.clobbers.is_empty() {
+                self.out.push_str(" : ");
+                for (i, clobber) in asm.clobbers.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.string(clobber.as_bytes());
+                }
+            }
+            self.out.push_str(") ");
+        }
+        // The body is raw text, it is printed as written
+        self.out.push('{');
+        self.out.push_str(&asm.text);
+        self.out.push('}');
+    }
+
+    fn path(&mut self, path: &Path) {
+        if path.here {
+            self.out.push_str("here.");
+        }
+        for (i, segment) in path.segments.iter().enumerate() {
+            if i > 0 {
+                self.out.push('.');
+            }
+            self.ident(segment);
+        }
+    }
+
+    fn escape(&mut self, c: char, quote: char) {
+        match c {
+            '\n' => self.out.push_str("\\n"),
+            '\t' => self.out.push_str("\\t"),
+            '\r' => self.out.push_str("\\r"),
+            '\0' => self.out.push_str("\\0"),
+            '\\' => self.out.push_str("\\\\"),
+            c if c == quote => {
+                self.out.push('\\');
+                self.out.push(c);
+            }
+            c if c.is_ascii_control() => self.out.push_str(&format!("\\x{:02x}", c as u8)),
+            c => self.out.push(c),
+        }
+    }
+
+    /// Valid UTF-8 is printed as text, any other byte as `\xNN`
+    fn string(&mut self, value: &[u8]) {
+        self.out.push('"');
+        for chunk in value.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                self.escape(c, '"');
+            }
+            for byte in chunk.invalid() {
+                self.out.push_str(&format!("\\x{byte:02x}"));
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn binary(&mut self, op: &str, lhs: &Expr, rhs: &Expr, prec: Prec) {
+        // `**` is right associative, the others left associative
+        let (left, right) = match prec {
+            Prec::Power => (Prec::Primary, Prec::Prefix),
+            Prec::Compare => (Prec::Compare, Prec::Sum),
+            Prec::Sum => (Prec::Sum, Prec::Product),
+            Prec::Product => (Prec::Product, Prec::Cast),
+            _ => (prec, Prec::Prefix),
+        };
+        self.expr(lhs, left);
+        self.out.push_str(&format!(" {op} "));
+        self.expr(rhs, right);
+    }
+
+    fn expr(&mut self, expr: &Expr, min: Prec) {
+        let prec = precedence(expr);
+        if prec < min {
+            self.out.push('(');
+            self.expr(expr, Prec::Lowest);
+            self.out.push(')');
+            return;
+        }
+        match expr {
+            Expr::Number(literal) => self.out.push_str(&literal.text),
+            Expr::Char(value) => {
+                self.out.push('\'');
+                if value.is_ascii() {
+                    self.escape(*value as char, '\'');
+                } else {
+                    self.out.push_str(&format!("\\x{value:02x}"));
+                }
+                self.out.push('\'');
+            }
+            Expr::Str(value) => self.string(value),
+            Expr::Ident(name) => self.ident(name),
+            Expr::Add(lhs, rhs) => self.binary("+", &lhs.1, &rhs.1, prec),
+            Expr::Sub(lhs, rhs) => self.binary("-", &lhs.1, &rhs.1, prec),
+            Expr::Mul(lhs, rhs) => self.binary("*", &lhs.1, &rhs.1, prec),
+            Expr::Div(lhs, rhs) => self.binary("/", &lhs.1, &rhs.1, prec),
+            Expr::Pow(lhs, rhs) => self.binary("**", &lhs.1, &rhs.1, prec),
+            Expr::Eq(lhs, rhs) => self.binary("==", &lhs.1, &rhs.1, prec),
+            Expr::NotEq(lhs, rhs) => self.binary("!=", &lhs.1, &rhs.1, prec),
+            Expr::Greater(lhs, rhs) => self.binary(">", &lhs.1, &rhs.1, prec),
+            Expr::Less(lhs, rhs) => self.binary("<", &lhs.1, &rhs.1, prec),
+            Expr::Neg(term) | Expr::Not(term) => {
+                self.out.push(if matches!(expr, Expr::Neg(_)) { '-' } else { '!' });
+                // `-a ** b` already reads as `-(a ** b)`
+                self.expr(&term.1, Prec::Power);
+            }
+            Expr::Cast(value, ty) => {
+                self.expr(&value.1, Prec::Cast);
+                self.out.push_str(" as ");
+                self.ty(&ty.1);
+            }
+            Expr::Call(path, args) => {
+                self.path(path);
+                self.out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(&arg.1, Prec::Lowest);
+                }
+                self.out.push(')');
+            }
+            // Statements only appear here in hand-built trees
+            stat => self.statement(stat),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(expr: Expr) -> Box<Spanned<Expr>> {
+        Box::new(Spanned(0..0, expr))
+    }
+
+    fn ident(name: &str) -> Box<Spanned<Expr>> {
+        node(Expr::Ident(name.into()))
+    }
+
+    #[test]
+    fn parenthesize_by_precedence() {
+        let sum = Expr::Add(ident("a"), ident("b"));
+        let expr = Expr::Mul(node(sum.clone()), node(Expr::Neg(node(Expr::Pow(ident("c"), ident("d"))))));
+        assert_eq!(print_expr(&expr).unwrap(), "(a + b) * -c ** d;");
+
+        let expr = Expr::Sub(ident("a"), node(Expr::Sub(ident("b"), ident("c"))));
+        assert_eq!(print_expr(&expr).unwrap(), "a - (b - c);");
+        let expr = Expr::Pow(node(Expr::Pow(ident("a"), ident("b"))), ident("c"));
+        assert_eq!(print_expr(&expr).unwrap(), "(a ** b) ** c;");
+        let expr = Expr::Neg(node(sum));
+        assert_eq!(print_expr(&expr).unwrap(), "-(a + b);");
+
+        let cast = |expr, ty| node(Expr::Cast(expr, Spanned(0..0, ty)));
+        let expr = Expr::Mul(ident("a"), cast(cast(node(Expr::Neg(ident("b"))), Type::U8), Type::I32));
+        assert_eq!(print_expr(&expr).unwrap(), "a * -b as u8 as i32;");
+        let expr = Expr::Neg(cast(node(Expr::Mul(ident("a"), ident("b"))), Type::U8));
+        assert_eq!(print_expr(&expr).unwrap(), "-((a * b) as u8);");
+        let expr = Expr::Cast(ident("a"), Spanned(0..0, Type::Named("if".into())));
+        assert_eq!(print_expr(&expr).unwrap(), "a as r#if;");
+    }
+
+    #[test]
+    fn refuse_to_print_loops() {
+        let expr = Expr::While(ident("a"), node(Expr::Break));
+        assert_eq!(print_expr(&expr), Err(PrintError::Loop));
+        let expr = Expr::Block(vec![Spanned(0..0, Expr::Continue), Spanned(0..0, Expr::Empty)]);
+        assert_eq!(print_expr(&expr), Err(PrintError::Loop));
+    }
+
+    #[test]
+    fn print_literals_and_names() {
+        let args = vec![
+            Spanned(0..0, Expr::Str(b"say \"hi\"\n\xff".to_vec())),
+            Spanned(0..0, Expr::Char(b'\'')),
+            Spanned(0..0, Expr::Char(0xff)),
+            Spanned(0..0, Expr::Ident("if".into())),
+        ];
+        let path = Path {
+            here: true,
+            segments: vec!["then".into()],
+        };
+        assert_eq!(
+            print_expr(&Expr::Call(path, args)).unwrap(),
+            r#"here.r#then("say \"hi\"\n\xff", '\'', '\xff', r#if);"#
+        );
+    }
+}
@@ -28,6 +28,23 @@ pub struct Location {
     source_key: SourceKey,
 }
 
+impl Location {
+    pub fn new(span: Span, source_key: impl Into<SourceKey>) -> Self {
+        Self {
+            span,
+            source_key: source_key.into(),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn source_key(&self) -> SourceKey {
+        self.source_key
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Report {
     level: Level,
@@ -61,6 +78,30 @@ impl Report {
 
         self
     }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn source_key(&self) -> SourceKey {
+        self.source_key
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.infos
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
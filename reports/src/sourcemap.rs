@@ -8,10 +8,82 @@ new_key_type! {
 #[derive(Debug, Default)]
 pub struct SourceMap(pub SlotMap<SourceKey, SourceDescription>);
 
+impl SourceMap {
+    pub fn insert(&mut self, source: SourceDescription) -> SourceKey {
+        self.0.insert(source)
+    }
+
+    pub fn get(&self, key: SourceKey) -> Option<&SourceDescription> {
+        self.0.get(key)
+    }
+
+    /// Line and column of the byte `offset` in the source behind `key`
+    pub fn line_col(&self, key: SourceKey, offset: usize) -> Option<LineCol> {
+        self.get(key).map(|source| source.line_col(offset))
+    }
+}
+
+/// 1-based position in a source, the column counts characters rather than
+/// bytes so that it matches what an editor shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct SourceDescription {
     pub url: SourceUrl,
-    pub source_code: String,
+    source_code: String,
+    /// Byte offset at which each line starts
+    line_starts: Vec<usize>,
+}
+
+impl SourceDescription {
+    pub fn new(url: impl Into<SourceUrl>, source_code: impl Into<String>) -> Self {
+        let source_code = source_code.into();
+        let line_starts = std::iter::once(0)
+            .chain(source_code.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            url: url.into(),
+            source_code,
+            line_starts,
+        }
+    }
+
+    pub fn source_code(&self) -> &str {
+        &self.source_code
+    }
+
+    /// Number of the line holding the byte `offset`, starting at 0. Offsets
+    /// past the end belong to the last line
+    pub fn line_index(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Text of the 0-based line `index` without its line terminator
+    pub fn line(&self, index: usize) -> Option<&str> {
+        let start = *self.line_starts.get(index)?;
+        let end = self.line_starts.get(index + 1).map_or(self.source_code.len(), |next| next - 1);
+        Some(self.source_code[start..end].trim_end_matches('\r'))
+    }
+
+    /// Line and column of the byte `offset`, an offset inside a multi-byte
+    /// character maps to that character
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let offset = offset.min(self.source_code.len());
+        let line = self.line_index(offset);
+        let start = self.line_starts[line];
+        let column = self.source_code[start..]
+            .char_indices()
+            .take_while(|(i, c)| start + i + c.len_utf8() <= offset)
+            .count();
+        LineCol {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,3 +100,33 @@ impl<'a> From<&'a str> for SourceUrl {
         Self::Sparse(value.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col_of_byte_offsets() {
+        let source = SourceDescription::new("test.se", "// héllo\r\nfunc ñ()\n\nx");
+        let at = |needle: &str| source.source_code().find(needle).unwrap();
+        assert_eq!(source.line_col(0), LineCol { line: 1, column: 1 });
+        assert_eq!(source.line_col(at("llo")), LineCol { line: 1, column: 6 });
+        assert_eq!(source.line_col(at("ñ")), LineCol { line: 2, column: 6 });
+        assert_eq!(source.line_col(at("()")), LineCol { line: 2, column: 7 });
+        // The second byte of `ñ` still points at `ñ`
+        assert_eq!(source.line_col(at("ñ") + 1), LineCol { line: 2, column: 6 });
+        assert_eq!(source.line_col(at("x")), LineCol { line: 4, column: 1 });
+        assert_eq!(source.line_col(usize::MAX), LineCol { line: 4, column: 2 });
+        assert_eq!(source.line(0), Some("// héllo"));
+        assert_eq!(source.line(2), Some(""));
+        assert_eq!(source.line(4), None);
+    }
+
+    #[test]
+    fn source_map_lookup() {
+        let mut map = SourceMap::default();
+        let key = map.insert(SourceDescription::new(SourceUrl::Anonymous, "a\nb"));
+        assert_eq!(map.line_col(key, 2), Some(LineCol { line: 2, column: 1 }));
+        assert_eq!(map.line_col(SourceKey::default(), 0), None);
+    }
+}
@@ -0,0 +1,5 @@
+//! Semantic passes run over the arena form of the tree before code
+//! generation, each one records what it learns in side tables
+
+pub mod resolve;
+pub mod typeck;
@@ -0,0 +1,528 @@
+//! Name resolution, binds every `Ident`, `Assign`, `Call` and asm operand
+//! to the symbol it refers to. Namespaces see all of their members regardless of order,
+//! locals are visible from their declaration to the end of the enclosing
+//! block and may shadow names of outer scopes. Prototypes share the symbol
+//! of the function they declare
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use ast::{
+    arena::{Arena, ExprId, Item, ItemId, Node, SideTable},
+    Path, Span, Spanned, Type,
+};
+use reports::{sourcemap::SourceKey, IntoReport, Label, Level, Location, Report, ReportContext};
+use slotmap::{new_key_type, SlotMap};
+
+new_key_type! {
+    pub struct SymbolId;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Namespace(ItemId),
+    Function(ItemId),
+    /// Position in the parameter list of the function
+    Param(ItemId, usize),
+    /// Introduced by the `Decl` node
+    Local(ExprId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Where the symbol is defined
+    pub span: Span,
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub symbols: SlotMap<SymbolId, Symbol>,
+    /// Symbol referred to by every resolved `Ident`, `Assign` and `Call`
+    pub uses: SideTable<ExprId, SymbolId>,
+    /// Symbol introduced by every `Decl`
+    pub decls: SideTable<ExprId, SymbolId>,
+    /// Variables bound by the operands of an `InlineAsm`, outputs first.
+    /// Missing if one of them is undefined
+    pub operands: SideTable<ExprId, Vec<SymbolId>>,
+    /// Symbol introduced by every function and namespace
+    pub items: SideTable<ItemId, SymbolId>,
+}
+
+impl Resolution {
+    /// Symbol `id` refers to or defines
+    pub fn symbol(&self, id: ExprId) -> Option<&Symbol> {
+        self.uses
+            .get(id)
+            .or_else(|| self.decls.get(id))
+            .map(|symbol| &self.symbols[*symbol])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveErrorKind {
+    UndefinedVariable(String),
+    UndefinedFunction(Path),
+    /// Name already bound in the same scope at `previous`
+    Duplicate { name: String, previous: Span },
+    /// Prototype and definition of a function disagree on its signature
+    Conflicting { name: String, previous: Span },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+    pub location: Location,
+}
+
+impl IntoReport for ResolveError {
+    fn into_report(self) -> Report {
+        let source_key = self.location.source_key();
+        let title = match &self.kind {
+            ResolveErrorKind::UndefinedVariable(name) => {
+                format!("cannot find variable `{name}` in this scope")
+            }
+            ResolveErrorKind::UndefinedFunction(path) => {
+                format!("cannot find function `{path}` in this scope")
+            }
+            ResolveErrorKind::Duplicate { name, .. } => format!("`{name}` is defined multiple times"),
+            ResolveErrorKind::Conflicting { name, .. } => format!("conflicting declarations of `{name}`"),
+        };
+        let report = Report::new(
+            Level::Error,
+            self.location.span().clone(),
+            source_key,
+            title,
+            None::<String>,
+        );
+        match self.kind {
+            ResolveErrorKind::Duplicate { name, previous } => report.with_label(Label::new(
+                format!("previous definition of `{name}` here"),
+                Some(previous),
+                source_key,
+            )),
+            ResolveErrorKind::Conflicting { name, previous } => report.with_label(Label::new(
+                format!("previous declaration of `{name}` here"),
+                Some(previous),
+                source_key,
+            )),
+            _ => report,
+        }
+    }
+}
+
+type Scope = HashMap<String, SymbolId>;
+
+/// Binds `name` in `scope` unless it is taken, in which case the symbol
+/// already bound is returned
+fn bind(scope: &mut Scope, name: &str, id: SymbolId) -> Option<SymbolId> {
+    match scope.entry(name.into()) {
+        Entry::Occupied(entry) => Some(*entry.get()),
+        Entry::Vacant(entry) => {
+            entry.insert(id);
+            None
+        }
+    }
+}
+
+/// Parameter types, return type and whether a function has a body
+fn signature(item: &Item) -> Option<(Vec<&Type>, &Type, bool)> {
+    match item {
+        Item::Function { params, ret, body, .. } => {
+            Some((params.iter().map(|(_, ty)| &ty.1).collect(), &ret.1, body.is_some()))
+        }
+        Item::Namespace { .. } => None,
+    }
+}
+
+pub struct Resolver<'a> {
+    arena: &'a Arena,
+    source_key: SourceKey,
+    errors: Vec<ResolveError>,
+    resolution: Resolution,
+    /// Members of every namespace, the top level is `None`
+    members: HashMap<Option<ItemId>, Scope>,
+    /// Enclosing namespaces, innermost last
+    namespaces: Vec<ItemId>,
+    /// Parameter and block scopes of the current function, innermost last
+    scopes: Vec<Scope>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(arena: &'a Arena, source_key: SourceKey) -> Self {
+        Self {
+            arena,
+            source_key,
+            errors: Vec::new(),
+            resolution: Resolution::default(),
+            members: HashMap::new(),
+            namespaces: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, kind: ResolveErrorKind, span: &Span) {
+        self.errors.push(ResolveError {
+            kind,
+            location: Location::new(span.clone(), self.source_key),
+        });
+    }
+
+    fn define(&mut self, name: &str, kind: SymbolKind, span: &Span) -> SymbolId {
+        self.resolution.symbols.insert(Symbol {
+            name: name.into(),
+            kind,
+            span: span.clone(),
+        })
+    }
+
+    fn duplicate(&mut self, id: SymbolId, previous: SymbolId) {
+        let symbol = &self.resolution.symbols[id];
+        let kind = ResolveErrorKind::Duplicate {
+            name: symbol.name.clone(),
+            previous: self.resolution.symbols[previous].span.clone(),
+        };
+        let span = symbol.span.clone();
+        self.error(kind, &span);
+    }
+
+    /// Binds every item of `items` and of the namespaces among them, so
+    /// calls may refer to functions defined further down
+    fn declare_items(&mut self, namespace: Option<ItemId>, items: &[ItemId]) {
+        for &item in items {
+            let Spanned(span, node) = &self.arena.items[item];
+            let kind = match node {
+                Item::Function { .. } => SymbolKind::Function(item),
+                Item::Namespace { items, .. } => {
+                    self.declare_items(Some(item), items);
+                    SymbolKind::Namespace(item)
+                }
+            };
+            let previous = self.members.get(&namespace).and_then(|scope| scope.get(node.name()));
+            if let Some(&previous) = previous {
+                if self.merge_prototype(previous, item) {
+                    continue;
+                }
+            }
+            let id = self.define(node.name(), kind, span);
+            self.resolution.items.insert(item, id);
+
+            let scope = self.members.entry(namespace).or_default();
+            if let Some(previous) = bind(scope, node.name(), id) {
+                self.duplicate(id, previous);
+            }
+        }
+    }
+
+    /// Lets `item` share the symbol `previous` if one of them is a
+    /// prototype, the symbol then points at the definition if there is one
+    fn merge_prototype(&mut self, previous: SymbolId, item: ItemId) -> bool {
+        let SymbolKind::Function(first) = self.resolution.symbols[previous].kind else {
+            return false;
+        };
+        let (Some((params, ret, body)), Some((other_params, other_ret, other_body))) =
+            (signature(&self.arena[first]), signature(&self.arena[item]))
+        else {
+            return false;
+        };
+        if body && other_body {
+            return false;
+        }
+
+        let Spanned(span, node) = &self.arena.items[item];
+        let symbol = &mut self.resolution.symbols[previous];
+        if params != other_params || ret != other_ret {
+            let kind = ResolveErrorKind::Conflicting {
+                name: node.name().into(),
+                previous: symbol.span.clone(),
+            };
+            self.error(kind, span);
+        } else if other_body {
+            symbol.kind = SymbolKind::Function(item);
+            symbol.span = span.clone();
+        }
+        self.resolution.items.insert(item, previous);
+        true
+    }
+
+    fn resolve_items(&mut self, items: &[ItemId]) {
+        for &item in items {
+            match &self.arena[item] {
+                Item::Function { params, body, .. } => {
+                    let mut scope = Scope::new();
+                    for (index, (name, _)) in params.iter().enumerate() {
+                        let id = self.define(&name.1, SymbolKind::Param(item, index), &name.0);
+                        if let Some(previous) = bind(&mut scope, &name.1, id) {
+                            self.duplicate(id, previous);
+                        }
+                    }
+                    if let Some(body) = body {
+                        self.scopes.push(scope);
+                        self.resolve_expr(*body);
+                        self.scopes.pop();
+                    }
+                }
+                Item::Namespace { items, .. } => {
+                    self.namespaces.push(item);
+                    self.resolve_items(items);
+                    self.namespaces.pop();
+                }
+            }
+        }
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<SymbolId> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+
+    /// `here.` paths are looked up in the current namespace only, other
+    /// paths from the current namespace outwards
+    fn lookup_function(&self, path: &Path) -> Option<SymbolId> {
+        let depth = self.namespaces.len();
+        let outer = if path.here { depth } else { 0 };
+        (outer..=depth).rev().find_map(|i| {
+            let start = i.checked_sub(1).map(|i| self.namespaces[i]);
+            self.lookup_in(start, &path.segments)
+        })
+    }
+
+    fn lookup_in(&self, mut namespace: Option<ItemId>, segments: &[String]) -> Option<SymbolId> {
+        let (name, prefix) = segments.split_last()?;
+        for segment in prefix {
+            let id = *self.members.get(&namespace)?.get(segment)?;
+            match self.resolution.symbols[id].kind {
+                SymbolKind::Namespace(item) => namespace = Some(item),
+                _ => return None,
+            }
+        }
+        let id = *self.members.get(&namespace)?.get(name)?;
+        matches!(self.resolution.symbols[id].kind, SymbolKind::Function(_)).then_some(id)
+    }
+
+    fn use_variable(&mut self, id: ExprId, name: &str) {
+        match self.lookup_variable(name) {
+            Some(symbol) => {
+                self.resolution.uses.insert(id, symbol);
+            }
+            None => {
+                let span = self.arena.span(id).clone();
+                self.error(ResolveErrorKind::UndefinedVariable(name.into()), &span);
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, id: ExprId) {
+        let span = self.arena.span(id);
+        match &self.arena[id] {
+            Node::Ident(name) => self.use_variable(id, name),
+            Node::Assign(name, value) => {
+                self.resolve_expr(*value);
+                self.use_variable(id, name);
+            }
+            Node::Decl(name, _, value) => {
+                // The value still sees what the name meant before
+                self.resolve_expr(*value);
+                let symbol = self.define(name, SymbolKind::Local(id), span);
+                self.resolution.decls.insert(id, symbol);
+                let scope = self.scopes.last_mut().expect("declarations live in functions");
+                if let Some(previous) = bind(scope, name, symbol) {
+                    self.duplicate(symbol, previous);
+                }
+            }
+            Node::Call(path, args) => {
+                for arg in args {
+                    self.resolve_expr(*arg);
+                }
+                match self.lookup_function(path) {
+                    Some(symbol) => {
+                        self.resolution.uses.insert(id, symbol);
+                    }
+                    None => self.error(ResolveErrorKind::UndefinedFunction(path.clone()), span),
+                }
+            }
+            Node::Block(_) | Node::For(..) => {
+                // The scope of a loop variable ends with the loop
+                self.scopes.push(Scope::new());
+                for child in self.arena.children(id) {
+                    self.resolve_expr(child);
+                }
+                self.scopes.pop();
+            }
+            Node::Item(item) => {
+                let namespace = self.namespaces.last().copied();
+                self.declare_items(namespace, &[*item]);
+                // Nested functions do not capture the locals around them
+                let scopes = std::mem::take(&mut self.scopes);
+                self.resolve_items(&[*item]);
+                self.scopes = scopes;
+            }
+            Node::InlineAsm(asm) => {
+                let mut operands = Vec::new();
+                for operand in asm.outputs.iter().chain(&asm.inputs) {
+                    match self.lookup_variable(&operand.variable) {
+                        Some(symbol) => operands.push(symbol),
+                        None => {
+                            let kind = ResolveErrorKind::UndefinedVariable(operand.variable.clone());
+                            self.error(kind, span);
+                        }
+                    }
+                }
+                if operands.len() == asm.outputs.len() + asm.inputs.len() {
+                    self.resolution.operands.insert(id, operands);
+                }
+            }
+            _ => {
+                for child in self.arena.children(id) {
+                    self.resolve_expr(child);
+                }
+            }
+        }
+    }
+
+    pub fn process(mut self, reports: &mut ReportContext) -> Resolution {
+        self.declare_items(None, &self.arena.root);
+        self.resolve_items(&self.arena.root);
+        reports.extend(self.errors.into_iter().map(IntoReport::into_report));
+        self.resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(src: &str) -> (Arena, Resolution, ReportContext) {
+        let (tokens, errors) = lexer::lex(src, SourceKey::default());
+        assert!(errors.is_empty(), "{errors:?}");
+        let mut reports = ReportContext::default();
+        let ast = parser::Parser::new(tokens, SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+        let arena = Arena::lower(&ast);
+        let resolution = Resolver::new(&arena, SourceKey::default()).process(&mut reports);
+        (arena, resolution, reports)
+    }
+
+    /// Node spanning exactly `text` at its first occurrence after `from`
+    fn find(arena: &Arena, src: &str, text: &str, from: usize) -> ExprId {
+        let start = from + src[from..].find(text).unwrap();
+        let span = start..start + text.len();
+        arena
+            .exprs
+            .iter()
+            .find(|(_, Spanned(node, _))| *node == span)
+            .map(|(id, _)| id)
+            .unwrap_or_else(|| panic!("no node `{text}` at {span:?}"))
+    }
+
+    #[test]
+    fn resolve_the_corpus() {
+        for file in ["../syntax/syntax0.se", "../syntax/syntax1.se", "../syntax/syntax2.se"] {
+            let src = std::fs::read_to_string(file).unwrap();
+            let (arena, resolution, reports) = resolve(&src);
+            assert!(!reports.has_reports(), "{file}: {:?}", &reports[..]);
+            for (id, Spanned(_, node)) in &arena.exprs {
+                if matches!(node, Node::Ident(_) | Node::Assign(..) | Node::Call(..)) {
+                    assert!(resolution.uses.contains_key(id), "{file}: {node:?}");
+                }
+                if matches!(node, Node::InlineAsm(asm) if asm.has_operands()) {
+                    assert!(resolution.operands.contains_key(id), "{file}: {node:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inner_scopes_shadow_outer_ones() {
+        let src = "func f(a:int) > int { var a:int = a + 1; { var a:int = 2; a = 3; } return a; }";
+        let (arena, resolution, reports) = resolve(src);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+
+        let param = find(&arena, src, "a", src.find("= a").unwrap());
+        assert!(matches!(resolution.symbol(param).unwrap().kind, SymbolKind::Param(_, 0)));
+
+        let outer = find(&arena, src, "var a:int = a + 1;", 0);
+        let inner = find(&arena, src, "var a:int = 2;", 0);
+        let assign = find(&arena, src, "a = 3", 0);
+        let returned = find(&arena, src, "a", src.find("return").unwrap());
+        assert_eq!(resolution.uses[assign], resolution.decls[inner]);
+        assert_eq!(resolution.uses[returned], resolution.decls[outer]);
+    }
+
+    #[test]
+    fn calls_see_the_whole_namespace() {
+        let src = "func main() > int { return m.f(); }
+namespace m {
+    func f() > int { return g(); }
+    func g() > int { return here.f() + here.main(); }
+}";
+        let (arena, resolution, reports) = resolve(src);
+        assert_eq!(reports.len(), 1, "{:?}", &reports[..]);
+        assert_eq!(reports[0].title(), "cannot find function `here.main` in this scope");
+
+        let call = find(&arena, src, "g()", 0);
+        let symbol = resolution.symbol(call).unwrap();
+        assert_eq!(symbol.name, "g");
+        assert!(matches!(symbol.kind, SymbolKind::Function(_)));
+        let call = find(&arena, src, "m.f()", 0);
+        assert_eq!(&src[resolution.symbol(call).unwrap().span.clone()], "func f() > int { return g(); }");
+    }
+
+    #[test]
+    fn prototypes_share_the_symbol_of_their_definition() {
+        let src = "func main() > int { return twice(1); }
+func twice(a:int) > int;
+func twice(b:int) > int { return b + b; }
+func twice(c:int) > int;
+func ext(c:char) > void;
+func ext(c:int) > void;
+func dup() > void { }
+func dup() > void { }";
+        let (arena, resolution, reports) = resolve(src);
+        let call = find(&arena, src, "twice(1)", 0);
+        let symbol = resolution.symbol(call).unwrap();
+        assert_eq!(&src[symbol.span.clone()], "func twice(b:int) > int { return b + b; }");
+        let twice: Vec<_> = arena
+            .items
+            .iter()
+            .filter(|(_, item)| item.1.name() == "twice")
+            .map(|(id, _)| resolution.items[id])
+            .collect();
+        assert_eq!(twice, [resolution.uses[call]; 3]);
+
+        let titles: Vec<_> = reports.iter().map(Report::title).collect();
+        assert_eq!(titles, ["conflicting declarations of `ext`", "`dup` is defined multiple times"]);
+        assert_eq!(&src[reports[0].span().clone()], "func ext(c:int) > void;");
+        let previous = reports[0].labels()[0].span.clone().unwrap();
+        assert_eq!(&src[previous], "func ext(c:char) > void;");
+    }
+
+    #[test]
+    fn report_undefined_and_duplicate_names() {
+        let src = "func f() > int { return y + g(); }
+func f() > void { }
+namespace n {
+    func h(a:int, a:int) > void { var z:int = 0; var z:int = a; asm (\"=r\"(w)) { nop } }
+}";
+        let (_, _, reports) = resolve(src);
+        let titles: Vec<_> = reports.iter().map(Report::title).collect();
+        assert_eq!(
+            titles,
+            [
+                "`f` is defined multiple times",
+                "cannot find variable `y` in this scope",
+                "cannot find function `g` in this scope",
+                "`a` is defined multiple times",
+                "`z` is defined multiple times",
+                "cannot find variable `w` in this scope",
+            ]
+        );
+        assert_eq!(&src[reports[0].span().clone()], "func f() > void { }");
+        let previous = reports[0].labels()[0].span.clone().unwrap();
+        assert_eq!(&src[previous], "func f() > int { return y + g(); }");
+        let params = src.find("a:int, a:int").unwrap();
+        assert_eq!(reports[3].span(), &(params + 7..params + 8));
+        let previous = reports[3].labels()[0].span.clone().unwrap();
+        assert_eq!(previous, params..params + 1);
+        assert_eq!(&src[reports[4].span().clone()], "var z:int = a;");
+    }
+}
@@ -0,0 +1,707 @@
+//! Type checking, infers the type of every expression from the symbols
+//! found by name resolution and checks declarations, assignments, returns,
+//! call arguments and conditions against the annotated types. Integer
+//! literals take the type their context expects if the value fits, other
+//! integers widen implicitly only when every value is kept and need an
+//! `as` otherwise
+
+use ast::{
+    arena::{Arena, BinaryOp, ExprId, Item, ItemId, Node, SideTable, UnaryOp},
+    Parameter, Span, Spanned, Type,
+};
+use reports::{sourcemap::SourceKey, IntoReport, Label, Level, Location, Report, ReportContext};
+
+use crate::resolve::{Resolution, SymbolKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeErrorKind {
+    /// `origin` is the annotation the expected type comes from, if any
+    Mismatch {
+        expected: Type,
+        found: Type,
+        origin: Option<Span>,
+    },
+    InvalidOperand { op: &'static str, found: Type },
+    InvalidCast { from: Type, to: Type },
+    /// Argument not converting to the parameter type written at `origin`
+    Argument {
+        expected: Type,
+        found: Type,
+        origin: Span,
+        callee: Callee,
+    },
+    Arity {
+        expected: usize,
+        found: usize,
+        callee: Callee,
+    },
+}
+
+/// Function called with wrong arguments, diagnostics quote its signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct Callee {
+    /// `func name(a: T, ...) > R`
+    pub signature: String,
+    /// From `func` to the end of the return type
+    pub span: Span,
+}
+
+impl Callee {
+    fn new(span: &Span, name: &str, params: &[Parameter], ret: &Spanned<Type>) -> Self {
+        let params = params
+            .iter()
+            .map(|(name, ty)| format!("{}: {}", name.1, ty.1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Self {
+            signature: format!("func {name}({params}) > {}", ret.1),
+            span: span.start..ret.0.end,
+        }
+    }
+
+    fn label(self, source_key: SourceKey) -> Label {
+        Label::new(format!("`{}` defined here", self.signature), Some(self.span), source_key)
+    }
+}
+
+fn arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".into(),
+        _ => format!("{count} arguments"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    pub location: Location,
+}
+
+impl IntoReport for TypeError {
+    fn into_report(self) -> Report {
+        let source_key = self.location.source_key();
+        let span = self.location.span().clone();
+        match self.kind {
+            TypeErrorKind::Mismatch {
+                expected,
+                found,
+                origin,
+            } => Report::new(
+                Level::Error,
+                span.clone(),
+                source_key,
+                "mismatched types",
+                Some(format!("expected `{expected}`, found `{found}`")),
+            )
+            .with_label(Label::new(format!("expected `{expected}`"), origin, source_key))
+            .with_label(Label::new(format!("found `{found}`"), Some(span), source_key)),
+            TypeErrorKind::InvalidOperand { op, found } => Report::new(
+                Level::Error,
+                span.clone(),
+                source_key,
+                format!("cannot apply `{op}` to `{found}`"),
+                None::<String>,
+            )
+            .with_label(Label::new(format!("found `{found}`"), Some(span), source_key)),
+            TypeErrorKind::InvalidCast { from, to } => Report::new(
+                Level::Error,
+                span.clone(),
+                source_key,
+                format!("cannot cast `{from}` as `{to}`"),
+                None::<String>,
+            )
+            .with_label(Label::new(format!("found `{from}`"), Some(span), source_key)),
+            TypeErrorKind::Argument {
+                expected,
+                found,
+                origin,
+                callee,
+            } => Report::new(
+                Level::Error,
+                span.clone(),
+                source_key,
+                "mismatched types",
+                Some(format!("expected `{expected}`, found `{found}`")),
+            )
+            .with_label(Label::new(format!("expected `{expected}`"), Some(origin), source_key))
+            .with_label(Label::new(format!("found `{found}`"), Some(span), source_key))
+            .with_label(callee.label(source_key)),
+            TypeErrorKind::Arity {
+                expected,
+                found,
+                callee,
+            } => Report::new(
+                Level::Error,
+                span,
+                source_key,
+                format!(
+                    "this function takes {} but {} {} supplied",
+                    arguments(expected),
+                    arguments(found),
+                    if found == 1 { "was" } else { "were" }
+                ),
+                Some(format!("the signature is `{}`", callee.signature)),
+            )
+            .with_label(callee.label(source_key)),
+        }
+    }
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Pow => "**",
+        BinaryOp::Eq => "==",
+        BinaryOp::NotEq => "!=",
+        BinaryOp::Greater => ">",
+        BinaryOp::Less => "<",
+    }
+}
+
+/// Type of an integer literal, the `hint` if the value fits in it
+fn literal_type(value: i64, hint: Option<&Type>) -> Type {
+    hint.into_iter()
+        .chain(&[Type::I16, Type::I32, Type::U32])
+        .find(|ty| fits(value, ty))
+        .cloned()
+        .unwrap_or(Type::I32)
+}
+
+/// Whether the integer literal `value` can be stored in `ty`
+fn fits(value: i64, ty: &Type) -> bool {
+    match ty {
+        Type::U8 => u8::try_from(value).is_ok(),
+        Type::I8 => i8::try_from(value).is_ok(),
+        Type::U16 => u16::try_from(value).is_ok(),
+        Type::I16 => i16::try_from(value).is_ok(),
+        Type::U32 => u32::try_from(value).is_ok(),
+        Type::I32 => i32::try_from(value).is_ok(),
+        _ => false,
+    }
+}
+
+pub struct TypeChecker<'a> {
+    arena: &'a Arena,
+    resolution: &'a Resolution,
+    source_key: SourceKey,
+    errors: Vec<TypeError>,
+    types: SideTable<ExprId, Type>,
+    /// Return type of the function being checked
+    ret: Option<&'a Spanned<Type>>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(arena: &'a Arena, resolution: &'a Resolution, source_key: SourceKey) -> Self {
+        Self {
+            arena,
+            resolution,
+            source_key,
+            errors: Vec::new(),
+            types: SideTable::new(),
+            ret: None,
+        }
+    }
+
+    fn error(&mut self, kind: TypeErrorKind, span: &Span) {
+        self.errors.push(TypeError {
+            kind,
+            location: Location::new(span.clone(), self.source_key),
+        });
+    }
+
+    fn check_items(&mut self, items: &[ItemId]) {
+        for &item in items {
+            match &self.arena[item] {
+                Item::Function { ret, body: Some(body), .. } => {
+                    let outer = self.ret.replace(ret);
+                    self.infer(*body);
+                    self.ret = outer;
+                }
+                Item::Function { body: None, .. } => {}
+                Item::Namespace { items, .. } => self.check_items(items),
+            }
+        }
+    }
+
+    /// Annotated type of the variable or parameter `id` refers to
+    fn variable(&self, id: ExprId) -> Option<&'a Spanned<Type>> {
+        let arena = self.arena;
+        let symbol = self.resolution.uses.get(id)?;
+        match self.resolution.symbols[*symbol].kind {
+            SymbolKind::Param(item, index) => match &arena[item] {
+                Item::Function { params, .. } => Some(&params[index].1),
+                Item::Namespace { .. } => None,
+            },
+            SymbolKind::Local(decl) => match &arena[decl] {
+                Node::Decl(_, ty, _) => Some(ty),
+                _ => None,
+            },
+            SymbolKind::Function(_) | SymbolKind::Namespace(_) => None,
+        }
+    }
+
+    /// Type of `id` if it does not convert to `expected`
+    fn check(&mut self, id: ExprId, expected: &Type) -> Option<Type> {
+        let found = self.infer_hinted(id, Some(expected))?;
+        (!found.widens_to(expected)).then_some(found)
+    }
+
+    /// Checks that `id` has type `expected`, which was written at `origin`
+    fn expect(&mut self, id: ExprId, expected: &Type, origin: Option<&Span>) {
+        if let Some(found) = self.check(id, expected) {
+            let kind = TypeErrorKind::Mismatch {
+                expected: expected.clone(),
+                found,
+                origin: origin.cloned(),
+            };
+            self.error(kind, self.arena.span(id));
+        }
+    }
+
+    /// Type both operands are converted to, integers are promoted to a
+    /// common type and a literal takes the type of the other side
+    fn unify(&mut self, lhs: ExprId, rhs: ExprId, hint: Option<&Type>) -> Option<Type> {
+        let (first, second) = match self.literal(lhs) {
+            Some(_) => (rhs, lhs),
+            None => (lhs, rhs),
+        };
+        let ty = self.infer_hinted(first, hint)?;
+        let other = self.infer_hinted(second, Some(&ty))?;
+        if let Some(common) = ty.promote(&other) {
+            return Some(common);
+        }
+        if ty != other {
+            let kind = TypeErrorKind::Mismatch {
+                expected: ty,
+                found: other,
+                origin: Some(self.arena.span(first).clone()),
+            };
+            self.error(kind, self.arena.span(second));
+            return None;
+        }
+        Some(ty)
+    }
+
+    fn infer(&mut self, id: ExprId) -> Option<Type> {
+        self.infer_hinted(id, None)
+    }
+
+    /// Value of an integer literal, negations of a literal fold into it so
+    /// that `-128` is range checked as a whole
+    fn literal(&self, id: ExprId) -> Option<i64> {
+        match &self.arena[id] {
+            Node::Number(literal) => Some(literal.value),
+            Node::Unary(UnaryOp::Neg, term) => self.literal(*term).map(|value| -value),
+            _ => None,
+        }
+    }
+
+    /// Infers the type of `id`, literals in arithmetic take the `hint`
+    /// type when they fit in it
+    fn infer_hinted(&mut self, id: ExprId, hint: Option<&Type>) -> Option<Type> {
+        let span = self.arena.span(id);
+        let ty = match &self.arena[id] {
+            Node::Number(literal) => literal_type(literal.value, hint),
+            Node::Unary(UnaryOp::Neg, term) if self.literal(*term).is_some() => {
+                let ty = literal_type(self.literal(id)?, hint);
+                self.types.insert(*term, ty.clone());
+                ty
+            }
+            Node::Char(_) => Type::U8,
+            Node::Str(_) => Type::Pointer(Box::new(Type::U8)),
+            Node::Ident(_) => self.variable(id)?.1.clone(),
+            Node::Binary(op, lhs, rhs) => {
+                let hint = match op {
+                    BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Greater | BinaryOp::Less => None,
+                    _ => hint,
+                };
+                let ty = self.unify(*lhs, *rhs, hint)?;
+                let valid = match op {
+                    BinaryOp::Eq | BinaryOp::NotEq => ty != Type::Void,
+                    _ => ty.is_integer(),
+                };
+                if !valid {
+                    let kind = TypeErrorKind::InvalidOperand {
+                        op: operator(*op),
+                        found: ty,
+                    };
+                    self.error(kind, span);
+                    return None;
+                }
+                match op {
+                    BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Greater | BinaryOp::Less => Type::Bool,
+                    _ => ty,
+                }
+            }
+            Node::Unary(op, term) => return self.infer_unary(id, *op, *term, hint, false),
+            Node::Cast(value, ty) => {
+                let from = match self.arena[*value] {
+                    // `-x as u8` asks for the wrapped around value
+                    Node::Unary(UnaryOp::Neg, term) if self.literal(term).is_none() => {
+                        self.infer_unary(*value, UnaryOp::Neg, term, None, true)?
+                    }
+                    _ => self.infer(*value)?,
+                };
+                if !from.casts_to(&ty.1) {
+                    let kind = TypeErrorKind::InvalidCast {
+                        from,
+                        to: ty.1.clone(),
+                    };
+                    self.error(kind, span);
+                }
+                ty.1.clone()
+            }
+            Node::Decl(_, ty, value) => {
+                self.expect(*value, &ty.1, Some(&ty.0));
+                Type::Void
+            }
+            Node::Assign(_, value) => {
+                match self.variable(id) {
+                    Some(ty) => self.expect(*value, &ty.1, Some(&ty.0)),
+                    None => {
+                        self.infer(*value);
+                    }
+                }
+                Type::Void
+            }
+            Node::Call(_, args) => return self.infer_call(id, args),
+            Node::If(cond, ..) | Node::While(cond, ..) => {
+                self.expect(*cond, &Type::Bool, None);
+                self.infer_children(id, 1);
+                Type::Void
+            }
+            Node::For(init, cond, ..) => {
+                self.infer(*init);
+                if !matches!(self.arena[*cond], Node::Empty) {
+                    self.expect(*cond, &Type::Bool, None);
+                }
+                self.infer_children(id, 2);
+                Type::Void
+            }
+            Node::Return(value) => {
+                let ret = self.ret.expect("returns live in functions");
+                match self.arena[*value] {
+                    Node::Empty if ret.1 != Type::Void => {
+                        let kind = TypeErrorKind::Mismatch {
+                            expected: ret.1.clone(),
+                            found: Type::Void,
+                            origin: Some(ret.0.clone()),
+                        };
+                        self.error(kind, span);
+                    }
+                    Node::Empty => {}
+                    _ => self.expect(*value, &ret.1, Some(&ret.0)),
+                }
+                Type::Void
+            }
+            Node::Item(item) => {
+                self.check_items(&[*item]);
+                Type::Void
+            }
+            Node::Block(_) => {
+                self.infer_children(id, 0);
+                Type::Void
+            }
+            Node::Break | Node::Continue | Node::InlineAsm(_) | Node::Empty => Type::Void,
+        };
+        self.types.insert(id, ty.clone());
+        Some(ty)
+    }
+
+    /// Unsigned values are only negated when `wrapping` around is asked for
+    fn infer_unary(
+        &mut self,
+        id: ExprId,
+        op: UnaryOp,
+        term: ExprId,
+        hint: Option<&Type>,
+        wrapping: bool,
+    ) -> Option<Type> {
+        let ty = self.infer_hinted(term, hint)?;
+        let valid = match op {
+            UnaryOp::Neg => ty.is_integer() && (ty.is_signed() || wrapping),
+            UnaryOp::Not => ty.is_integer() || ty == Type::Bool,
+        };
+        if !valid {
+            let op = match op {
+                UnaryOp::Neg => "-",
+                UnaryOp::Not => "!",
+            };
+            self.error(TypeErrorKind::InvalidOperand { op, found: ty }, self.arena.span(id));
+            return None;
+        }
+        self.types.insert(id, ty.clone());
+        Some(ty)
+    }
+
+    fn infer_children(&mut self, id: ExprId, skip: usize) {
+        for child in self.arena.children(id).skip(skip) {
+            self.infer(child);
+        }
+    }
+
+    fn infer_call(&mut self, id: ExprId, args: &[ExprId]) -> Option<Type> {
+        let arena = self.arena;
+        let callee = self
+            .resolution
+            .uses
+            .get(id)
+            .map(|symbol| self.resolution.symbols[*symbol].kind);
+        let Some(SymbolKind::Function(item)) = callee else {
+            self.infer_children(id, 0);
+            return None;
+        };
+        let Spanned(span, Item::Function { name, ret, params, .. }) = &arena.items[item] else {
+            return None;
+        };
+        let callee = Callee::new(span, name, params, ret);
+        if args.len() != params.len() {
+            let kind = TypeErrorKind::Arity {
+                expected: params.len(),
+                found: args.len(),
+                callee: callee.clone(),
+            };
+            self.error(kind, arena.span(id));
+        }
+        for (index, arg) in args.iter().enumerate() {
+            let Some((_, ty)) = params.get(index) else {
+                self.infer(*arg);
+                continue;
+            };
+            if let Some(found) = self.check(*arg, &ty.1) {
+                let kind = TypeErrorKind::Argument {
+                    expected: ty.1.clone(),
+                    found,
+                    origin: ty.0.clone(),
+                    callee: callee.clone(),
+                };
+                self.error(kind, arena.span(*arg));
+            }
+        }
+        self.types.insert(id, ret.1.clone());
+        Some(ret.1.clone())
+    }
+
+    pub fn process(mut self, reports: &mut ReportContext) -> SideTable<ExprId, Type> {
+        self.check_items(&self.arena.root);
+        reports.extend(self.errors.into_iter().map(IntoReport::into_report));
+        self.types
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::Resolver;
+
+    fn check(src: &str) -> (Arena, SideTable<ExprId, Type>, ReportContext) {
+        let (tokens, errors) = lexer::lex(src, SourceKey::default());
+        assert!(errors.is_empty(), "{errors:?}");
+        let mut reports = ReportContext::default();
+        let ast = parser::Parser::new(tokens, SourceKey::default()).process(&mut reports);
+        let arena = Arena::lower(&ast);
+        let resolution = Resolver::new(&arena, SourceKey::default()).process(&mut reports);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+        let types = TypeChecker::new(&arena, &resolution, SourceKey::default()).process(&mut reports);
+        (arena, types, reports)
+    }
+
+    /// Type of the node spanning exactly `text`
+    fn type_of<'t>(arena: &Arena, types: &'t SideTable<ExprId, Type>, src: &str, text: &str) -> &'t Type {
+        let start = src.find(text).unwrap();
+        let (id, _) = arena
+            .exprs
+            .iter()
+            .find(|(_, Spanned(span, _))| *span == (start..start + text.len()))
+            .unwrap_or_else(|| panic!("no node `{text}`"));
+        &types[id]
+    }
+
+    fn text<'s>(src: &'s str, span: &Option<Span>) -> &'s str {
+        &src[span.clone().unwrap()]
+    }
+
+    #[test]
+    fn infer_expression_types() {
+        let src = "func f(a:u8, p:*char) > bool {
+    var c:long = 70000 * 2;
+    var q:*u8 = \"s\";
+    c = c + 1;
+    if (a > 5) then return p == q;
+    return g(a, 'x') != -3;
+}
+func g(a:u8, b:char) > i8 { return 1; }";
+        let (arena, types, reports) = check(src);
+        assert!(!reports.has_reports(), "{:?}", &reports[..]);
+        assert_eq!(type_of(&arena, &types, src, "70000 * 2"), &Type::I32);
+        assert_eq!(type_of(&arena, &types, src, "c + 1"), &Type::I32);
+        assert_eq!(type_of(&arena, &types, src, "5"), &Type::U8);
+        assert_eq!(type_of(&arena, &types, src, "p == q"), &Type::Bool);
+        assert_eq!(type_of(&arena, &types, src, "g(a, 'x')"), &Type::I8);
+        assert_eq!(type_of(&arena, &types, src, "-3"), &Type::I8);
+    }
+
+    #[test]
+    fn report_mismatched_types() {
+        let src = "func f(a:int, b:int) > void {
+    var x:int = \"hello\";
+    var y:u8 = 300;
+    x = f(-1 as u16, 1);
+    if a + b then return a+b;
+    return;
+}
+func g() > *u8 { return; }";
+        let (_, _, reports) = check(src);
+        let found: Vec<_> = reports
+            .iter()
+            .map(|report| (&src[report.span().clone()], report.description().unwrap()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("\"hello\"", "expected `i16`, found `*u8`"),
+                ("300", "expected `u8`, found `i16`"),
+                ("-1 as u16", "expected `i16`, found `u16`"),
+                ("f(-1 as u16, 1)", "expected `i16`, found `void`"),
+                ("a + b", "expected `bool`, found `i16`"),
+                ("a+b", "expected `void`, found `i16`"),
+                ("return;", "expected `*u8`, found `void`"),
+            ]
+        );
+
+        let labels = reports[0].labels();
+        assert_eq!(labels[0].info, "expected `i16`");
+        assert_eq!(text(src, &labels[0].span), "int");
+        assert_eq!(labels[1].info, "found `*u8`");
+        assert_eq!(text(src, &labels[1].span), "\"hello\"");
+        assert_eq!(text(src, &reports[2].labels()[0].span), "int");
+        assert_eq!(reports[4].labels()[0].span, None);
+        assert_eq!(text(src, &reports[6].labels()[0].span), "*u8");
+    }
+
+    #[test]
+    fn check_call_signatures() {
+        let src = "func main() > int {
+    return add(1) + m.id(1, 2, 3) as i16 + add(1, \"s\") + add();
+}
+
+/// Adds
+func add(a:int, b:int) > int { return a + b; }
+
+namespace m { func id(x:u8) > u8 { return x; } }";
+        let (_, _, reports) = check(src);
+        let found: Vec<_> = reports
+            .iter()
+            .map(|report| (report.title(), &src[report.span().clone()]))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("this function takes 2 arguments but 1 argument was supplied", "add(1)"),
+                ("this function takes 1 argument but 3 arguments were supplied", "m.id(1, 2, 3)"),
+                ("mismatched types", "\"s\""),
+                ("this function takes 2 arguments but 0 arguments were supplied", "add()"),
+            ]
+        );
+
+        assert_eq!(reports[0].description(), Some("the signature is `func add(a: i16, b: i16) > i16`"));
+        let label = &reports[0].labels()[0];
+        assert_eq!(label.info, "`func add(a: i16, b: i16) > i16` defined here");
+        assert_eq!(text(src, &label.span), "func add(a:int, b:int) > int");
+        assert_eq!(reports[1].description(), Some("the signature is `func id(x: u8) > u8`"));
+
+        let labels = reports[2].labels();
+        assert_eq!(reports[2].description(), Some("expected `i16`, found `*u8`"));
+        assert_eq!(text(src, &labels[0].span), "int");
+        assert_eq!(labels[2].info, "`func add(a: i16, b: i16) > i16` defined here");
+    }
+
+    #[test]
+    fn report_invalid_operands() {
+        let src = "func f(p:*u8, b:bool) > void {
+    var x:bool = p < p;
+    var y:bool = -b;
+    var z:*u8 = p + 1;
+    var w:bool = 1 as bool;
+}";
+        let (_, _, reports) = check(src);
+        let titles: Vec<_> = reports.iter().map(Report::title).collect();
+        assert_eq!(
+            titles,
+            [
+                "cannot apply `<` to `*u8`",
+                "cannot apply `-` to `bool`",
+                "mismatched types",
+                "cannot cast `i16` as `bool`",
+            ]
+        );
+        assert_eq!(reports[2].description(), Some("expected `*u8`, found `i16`"));
+        assert_eq!(text(src, &reports[2].labels()[0].span), "p");
+    }
+
+    #[test]
+    fn negative_literals_keep_their_sign() {
+        let src = "func f(u:u8) > void {
+    var y:u8 = -1;
+    var w:u16 = -(1);
+    var z:i8 = -128;
+    var n:i16 = -u;
+    var m:u8 = -u as u8;
+}";
+        let (arena, types, reports) = check(src);
+        let found: Vec<_> = reports
+            .iter()
+            .map(|report| (&src[report.span().clone()], report.title()))
+            .collect();
+        assert_eq!(
+            found,
+            [("-1", "mismatched types"), ("-(1)", "mismatched types"), ("-u", "cannot apply `-` to `u8`")]
+        );
+        assert_eq!(reports[0].description(), Some("expected `u8`, found `i16`"));
+        assert_eq!(type_of(&arena, &types, src, "-128"), &Type::I8);
+        assert_eq!(type_of(&arena, &types, src, "-u as u8"), &Type::U8);
+    }
+
+    #[test]
+    fn promote_mixed_integers() {
+        let src = "func f(a:u8, b:i16, c:u16, d:i32, e:i8) > i32 {
+    var x:i16 = a + b;
+    var y:u16 = b + c;
+    var z:i32 = c + d;
+    var w:u32 = a as u32 + d as u32;
+    var v:u8 = b;
+    var u:i16 = c;
+    return e * a;
+}";
+        let (arena, types, reports) = check(src);
+        for (text, ty) in [
+            ("a + b", Type::I16),
+            ("b + c", Type::U16),
+            ("c + d", Type::I32),
+            ("a as u32 + d as u32", Type::U32),
+            ("e * a", Type::U8),
+        ] {
+            assert_eq!(type_of(&arena, &types, src, text), &ty, "{text}");
+        }
+        let found: Vec<_> = reports.iter().map(|report| report.description().unwrap()).collect();
+        assert_eq!(found, ["expected `u8`, found `i16`", "expected `i16`, found `u16`"]);
+    }
+
+    #[test]
+    fn promotion_rules() {
+        assert_eq!(Type::U8.promote(&Type::I8), Some(Type::U8));
+        assert_eq!(Type::U8.promote(&Type::I16), Some(Type::I16));
+        assert_eq!(Type::U32.promote(&Type::I16), Some(Type::U32));
+        assert_eq!(Type::Bool.promote(&Type::I16), None);
+        assert!(Type::U8.widens_to(&Type::I16));
+        assert!(Type::I8.widens_to(&Type::I32));
+        assert!(!Type::U16.widens_to(&Type::I16));
+        assert!(!Type::I8.widens_to(&Type::U32));
+        assert!(!Type::I32.widens_to(&Type::I16));
+        assert!(Type::Pointer(Box::new(Type::U8)).casts_to(&Type::U16));
+        assert!(!Type::I16.casts_to(&Type::Bool));
+    }
+}